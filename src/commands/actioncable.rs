@@ -0,0 +1,196 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Signature, Span, Spanned, SyntaxShape, Type, Value,
+};
+use serde_json::json;
+
+use super::{connection_flags, input_values, open_session, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::Session,
+    },
+    WebSocketPlugin,
+};
+
+/// The subprotocols Rails offers; the second one makes servers reject unknown clients.
+const SUBPROTOCOLS: [&str; 2] = ["actioncable-v1-json", "actioncable-unsupported"];
+
+pub struct WebSocketActionCable;
+
+impl PluginCommand for WebSocketActionCable {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws actioncable"
+    }
+
+    fn description(&self) -> &str {
+        "subscribe to a Rails ActionCable channel and stream its messages"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Pipeline input is performed on the channel after subscribing: each record needs an \
+         `action` field and its other fields are sent as the action's data. Use --headers to \
+         pass the Cookie or Authorization header the cable connection authenticates with."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::list(Type::Any)),
+                    (Type::record(), Type::list(Type::Any)),
+                    (Type::list(Type::record()), Type::list(Type::Any)),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL of the cable endpoint (ws:// or wss://).",
+                )
+                .required_named(
+                    "channel",
+                    SyntaxShape::String,
+                    "the channel class to subscribe to, e.g. ChatChannel",
+                    Some('c'),
+                )
+                .named(
+                    "params",
+                    SyntaxShape::Record(vec![]),
+                    "parameters added to the subscription identifier",
+                    Some('p'),
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let channel: Spanned<String> = call
+            .get_flag("channel")?
+            .ok_or_else(|| LabeledError::new("Missing required flag --channel"))?;
+        let params: Option<Value> = call.get_flag("params")?;
+        let performs = input_values(input)?
+            .iter()
+            .map(perform_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut identifier = match params.as_ref().map(value_to_json).transpose()? {
+            Some(serde_json::Value::Object(params)) => params,
+            _ => serde_json::Map::new(),
+        };
+        identifier.insert("channel".into(), json!(channel.item));
+        // Rails matches subscriptions by this exact string, so it is encoded once and reused
+        let identifier = serde_json::Value::Object(identifier).to_string();
+
+        let subprotocols = SUBPROTOCOLS.map(String::from);
+        let mut cable = Cable {
+            session: open_session(call, engine, &subprotocols)?,
+            identifier,
+        };
+
+        cable.subscribe(channel.span)?;
+        for data in performs {
+            cable.perform(data)?;
+        }
+
+        let head = call.head;
+        Ok(stream_values(
+            move || Ok(cable.next()?.map(|message| json_to_value(message, head))),
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// A subscription to one channel on a cable connection.
+struct Cable {
+    session: Session,
+    identifier: String,
+}
+
+impl Cable {
+    fn command(&mut self, command: &str, data: Option<String>) -> Result<(), LabeledError> {
+        let mut message = json!({ "command": command, "identifier": self.identifier });
+        if let Some(data) = data {
+            message["data"] = json!(data);
+        }
+        self.session.send_json(&message)
+    }
+
+    fn subscribe(&mut self, channel_span: Span) -> Result<(), LabeledError> {
+        log::debug!("Subscribing to {}", self.identifier);
+        self.command("subscribe", None)?;
+
+        loop {
+            let Some(message) = self.session.recv_json()? else {
+                return Err(
+                    LabeledError::new("ActionCable subscription failed").with_label(
+                        "connection ended before the subscription was confirmed",
+                        channel_span,
+                    ),
+                );
+            };
+            match message["type"].as_str() {
+                Some("confirm_subscription") if self.is_ours(&message) => {
+                    log::debug!("Subscription confirmed");
+                    return Ok(());
+                }
+                Some("reject_subscription") if self.is_ours(&message) => {
+                    return Err(LabeledError::new("ActionCable subscription rejected")
+                        .with_label("the server rejected this channel", channel_span))
+                }
+                Some("disconnect") => return Err(disconnected(&message, channel_span)),
+                _ => log::trace!("Ignoring message while subscribing: {message}"),
+            }
+        }
+    }
+
+    fn perform(&mut self, data: serde_json::Value) -> Result<(), LabeledError> {
+        log::debug!("Performing {}", data["action"]);
+        self.command("message", Some(data.to_string()))
+    }
+
+    fn is_ours(&self, message: &serde_json::Value) -> bool {
+        message["identifier"].as_str() == Some(self.identifier.as_str())
+    }
+
+    /// The next `message` payload for the subscription, skipping control messages.
+    fn next(&mut self) -> Result<Option<serde_json::Value>, LabeledError> {
+        loop {
+            let Some(mut message) = self.session.recv_json()? else {
+                return Ok(None);
+            };
+            match message["type"].as_str() {
+                Some("welcome") | Some("ping") | Some("confirm_subscription") => continue,
+                Some("disconnect") => {
+                    return Err(disconnected(&message, self.session.span()));
+                }
+                _ if self.is_ours(&message) && message.get("message").is_some() => {
+                    return Ok(Some(message["message"].take()));
+                }
+                _ => log::trace!("Ignoring message: {message}"),
+            }
+        }
+    }
+}
+
+fn disconnected(message: &serde_json::Value, span: Span) -> LabeledError {
+    let reason = message["reason"].as_str().unwrap_or("unknown");
+    LabeledError::new(format!("ActionCable disconnected: {reason}"))
+        .with_label("the server closed the cable", span)
+}
+
+fn perform_entry(value: &Value) -> Result<serde_json::Value, LabeledError> {
+    let data = value_to_json(value)?;
+    if !data["action"].is_string() {
+        return Err(LabeledError::new("Invalid perform")
+            .with_label("expected a record with an `action` field", value.span()));
+    }
+    Ok(data)
+}
@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use serde_json::json;
+
+use super::{connection_flags, input_values, open_session, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::Session,
+    },
+    WebSocketPlugin,
+};
+
+pub struct WebSocketCdp;
+
+impl PluginCommand for WebSocketCdp {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws cdp"
+    }
+
+    fn description(&self) -> &str {
+        "send a Chrome DevTools Protocol command and return its result"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The URL is a DevTools websocket, e.g. the webSocketDebuggerUrl from /json/version. \
+         Without a method, a list of {method, params} records is read from the pipeline and \
+         sent in order on the same connection. With --events, the matching events are streamed \
+         after the commands complete instead of returning their results; `Domain.*` matches a \
+         whole domain."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::Any),
+                    (Type::list(Type::record()), Type::list(Type::Any)),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The DevTools websocket URL (ws:// or wss://).",
+                )
+                .optional(
+                    "method",
+                    SyntaxShape::String,
+                    "The command to send, as Domain.method.",
+                )
+                .named(
+                    "params",
+                    SyntaxShape::Record(vec![]),
+                    "parameters of the command",
+                    Some('p'),
+                )
+                .named(
+                    "session-id",
+                    SyntaxShape::String,
+                    "send the commands to this target session",
+                    Some('s'),
+                )
+                .named(
+                    "attach",
+                    SyntaxShape::String,
+                    "attach to this target id first and send the commands to its session",
+                    Some('a'),
+                )
+                .named(
+                    "events",
+                    SyntaxShape::List(Box::new(SyntaxShape::String)),
+                    "event names to stream after the commands, e.g. [Network.requestWillBeSent]",
+                    Some('e'),
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let method: Option<Spanned<String>> = call.opt(1)?;
+        let params: Option<Value> = call.get_flag("params")?;
+        let session_id: Option<String> = call.get_flag("session-id")?;
+        let attach: Option<Spanned<String>> = call.get_flag("attach")?;
+        let events: Option<Vec<String>> = call.get_flag("events")?;
+
+        let single = method.is_some();
+        let commands = match method {
+            Some(method) => vec![(method, params.as_ref().map(value_to_json).transpose()?)],
+            None => {
+                let commands = input_values(input)?
+                    .iter()
+                    .map(command_entry)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if commands.is_empty() {
+                    return Err(LabeledError::new("Missing method")
+                        .with_label("a method is required", call.head)
+                        .with_help("pipe in a list of {method, params} records to send several"));
+                }
+                commands
+            }
+        };
+
+        let mut client = CdpClient::new(open_session(call, engine, &[])?, events.clone());
+
+        let session_id = match attach {
+            Some(target) => {
+                let result = client.call(
+                    "Target.attachToTarget",
+                    json!({ "targetId": target.item, "flatten": true }),
+                    None,
+                    target.span,
+                )?;
+                let session_id = result["sessionId"].as_str().map(str::to_string);
+                log::debug!(
+                    "Attached to target {} as session {session_id:?}",
+                    target.item
+                );
+                session_id
+            }
+            None => session_id,
+        };
+
+        let head = call.head;
+        let mut results = vec![];
+        for (method, params) in commands {
+            let result = client.call(
+                &method.item,
+                params.unwrap_or_else(|| json!({})),
+                session_id.as_deref(),
+                method.span,
+            )?;
+            results.push(json_to_value(result, head));
+        }
+
+        if events.is_some() {
+            return Ok(stream_values(
+                move || Ok(client.event()?.map(|event| event.into_value(head))),
+                head,
+                engine.signals().clone(),
+            ));
+        }
+
+        Ok(PipelineData::Value(
+            if single {
+                results.remove(0)
+            } else {
+                Value::list(results, head)
+            },
+            None,
+        ))
+    }
+}
+
+struct CdpEvent {
+    method: String,
+    params: serde_json::Value,
+    session_id: Option<String>,
+}
+
+impl CdpEvent {
+    fn into_value(self, span: Span) -> Value {
+        let mut record = Record::new();
+        record.push("method", Value::string(self.method, span));
+        record.push("params", json_to_value(self.params, span));
+        record.push(
+            "sessionId",
+            self.session_id
+                .map(|id| Value::string(id, span))
+                .unwrap_or_else(|| Value::nothing(span)),
+        );
+        Value::record(record, span)
+    }
+}
+
+/// Sends CDP commands with increasing ids and keeps the events the caller asked for.
+struct CdpClient {
+    session: Session,
+    next_id: u64,
+    filters: Option<Vec<String>>,
+    pending: VecDeque<CdpEvent>,
+}
+
+impl CdpClient {
+    fn new(session: Session, filters: Option<Vec<String>>) -> Self {
+        Self {
+            session,
+            next_id: 1,
+            filters,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn call(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+        session_id: Option<&str>,
+        span: Span,
+    ) -> Result<serde_json::Value, LabeledError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut command = json!({ "id": id, "method": method, "params": params });
+        if let Some(session_id) = session_id {
+            command["sessionId"] = json!(session_id);
+        }
+        log::debug!("Sending CDP command: {command}");
+        self.session.send_json(&command)?;
+
+        loop {
+            let Some(mut message) = self.session.recv_json()? else {
+                let reason = if self.session.timed_out() {
+                    "timed out"
+                } else {
+                    "connection closed"
+                };
+                return Err(LabeledError::new(format!("No CDP response: {reason}"))
+                    .with_label(format!("{reason} before this command was answered"), span));
+            };
+            if message["id"] == json!(id) {
+                if let Some(error) = message.get("error") {
+                    let code = error["code"].as_i64().unwrap_or_default();
+                    let text = error["message"].as_str().unwrap_or("unknown error");
+                    let mut labeled = LabeledError::new(format!("CDP error {code}: {text}"))
+                        .with_label(text.to_string(), span);
+                    if let Some(data) = error["data"].as_str() {
+                        labeled = labeled.with_help(data.to_string());
+                    }
+                    return Err(labeled);
+                }
+                return Ok(message["result"].take());
+            }
+            if let Some(event) = self.matching_event(message) {
+                self.pending.push_back(event);
+            }
+        }
+    }
+
+    /// The next event matching the `--events` filters.
+    fn event(&mut self) -> Result<Option<CdpEvent>, LabeledError> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+        while let Some(message) = self.session.recv_json()? {
+            if let Some(event) = self.matching_event(message) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    fn matching_event(&self, mut message: serde_json::Value) -> Option<CdpEvent> {
+        let method = message["method"].as_str()?.to_string();
+        let filters = self.filters.as_ref()?;
+        let matches = filters
+            .iter()
+            .any(|filter| match filter.strip_suffix(".*") {
+                Some(domain) => method
+                    .strip_prefix(domain)
+                    .is_some_and(|rest| rest.starts_with('.')),
+                None => *filter == method,
+            });
+        if !matches {
+            log::trace!("Ignoring event {method}");
+            return None;
+        }
+        Some(CdpEvent {
+            method,
+            params: message["params"].take(),
+            session_id: message["sessionId"].as_str().map(str::to_string),
+        })
+    }
+}
+
+fn command_entry(
+    value: &Value,
+) -> Result<(Spanned<String>, Option<serde_json::Value>), LabeledError> {
+    let invalid = || {
+        LabeledError::new("Invalid CDP command").with_label(
+            "expected a record with a `method` and optional `params`",
+            value.span(),
+        )
+    };
+    let record = value.as_record().map_err(|_| invalid())?;
+    let method = record.get("method").ok_or_else(invalid)?;
+    let params = record.get("params").map(value_to_json).transpose()?;
+    Ok((
+        Spanned {
+            item: method.coerce_string()?,
+            span: method.span(),
+        },
+        params,
+    ))
+}
@@ -0,0 +1,308 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+
+use super::input_values;
+use tungstenite::protocol::frame::coding::{Data, OpCode};
+
+use crate::{
+    ws::frame::{self, Frame, FrameError, FrameHeader, Sequence},
+    WebSocketPlugin,
+};
+
+const FIELDS: [&str; 7] = ["fin", "rsv1", "rsv2", "rsv3", "opcode", "mask", "payload"];
+
+pub struct ToWsFrame;
+
+impl PluginCommand for ToWsFrame {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "to ws-frame"
+    }
+
+    fn description(&self) -> &str {
+        "encode records as raw WebSocket frames"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Each record is {fin, rsv1, rsv2, rsv3, opcode, mask, payload}, where only opcode is \
+         required. opcode is a name like text or ping, or any number from 0 to 15. mask is a \
+         4-byte binary key, or true for a random one; without it the frame is not masked. \
+         fin defaults to true and the RSV bits to false. A list of records is encoded back to \
+         back. Frames are checked the way a live connection reads them, so RSV bits, reserved \
+         opcodes, oversized or fragmented control frames, continuation frames out of place and \
+         mixed masking are errors; --lenient builds them anyway."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(PluginCommand::name(self))
+            .input_output_types(vec![
+                (Type::record(), Type::Binary),
+                (Type::list(Type::record()), Type::Binary),
+            ])
+            .switch(
+                "lenient",
+                "build frames that break the protocol instead of failing",
+                Some('l'),
+            )
+            .category(Category::Formats)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let lenient = call.has_flag("lenient")?;
+        let mut sequence = Sequence::default();
+        let mut output = vec![];
+        for value in input_values(input)? {
+            let frame = frame_from_value(&value)?;
+            if !lenient {
+                frame::validate(&frame)
+                    .and_then(|()| sequence.check(&frame))
+                    .map_err(|e| {
+                        LabeledError::new("Invalid WebSocket frame")
+                            .with_label(e.to_string(), value.span())
+                            .with_help("use --lenient to build it anyway")
+                    })?;
+            }
+            output.extend(frame::encode(frame));
+        }
+        Ok(PipelineData::Value(Value::binary(output, call.head), None))
+    }
+}
+
+pub struct FromWsFrames;
+
+impl PluginCommand for FromWsFrames {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "from ws-frames"
+    }
+
+    fn description(&self) -> &str {
+        "decode raw WebSocket frames into a table"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Outputs one {fin, rsv1, rsv2, rsv3, opcode, mask, payload} record per frame, with the \
+         payload unmasked. The frames must all come from the same end of a connection: by \
+         default the first frame decides whether they must be masked, and --sender client or \
+         server says so up front. Frames are checked the way a live connection reads them: \
+         RSV bits, oversized or fragmented control frames and continuation frames out of \
+         place are errors unless --lenient is given. Truncated frames and reserved opcodes \
+         are always an error."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(PluginCommand::name(self))
+            .input_output_types(vec![(Type::Binary, Type::table())])
+            .named(
+                "sender",
+                SyntaxShape::String,
+                "which end sent the frames: client (masked) or server (not masked)",
+                Some('s'),
+            )
+            .switch(
+                "lenient",
+                "decode frames that break the protocol instead of failing",
+                Some('l'),
+            )
+            .category(Category::Formats)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let head = call.head;
+        let (data, span) = match input {
+            PipelineData::Value(Value::Binary { val, internal_span }, ..) => (val, internal_span),
+            PipelineData::ByteStream(stream, ..) => {
+                let span = stream.span();
+                (stream.into_bytes()?, span)
+            }
+            other => {
+                return Err(LabeledError::new("Input must be binary")
+                    .with_label("Unsupported input type", other.span().unwrap_or(head)))
+            }
+        };
+        let lenient = call.has_flag("lenient")?;
+        let mut sequence = Sequence::new(sender(call)?);
+
+        let mut frames = vec![];
+        let mut offset = 0;
+        while offset < data.len() {
+            let invalid = |e: FrameError| {
+                let title = match e {
+                    FrameError::Incomplete { .. } => "Truncated WebSocket frame",
+                    _ => "Invalid WebSocket frame",
+                };
+                LabeledError::new(title).with_label(
+                    format!("frame {} at byte {offset}: {e}", frames.len()),
+                    span,
+                )
+            };
+            let (frame, len) = frame::decode(&data[offset..]).map_err(invalid)?;
+            if !lenient {
+                frame::validate(&frame)
+                    .and_then(|()| sequence.check(&frame))
+                    .map_err(invalid)?;
+            }
+            frames.push(frame_value(frame, head));
+            offset += len;
+        }
+
+        Ok(PipelineData::Value(Value::list(frames, head), None))
+    }
+}
+
+/// Whether `--sender` requires the frames to be masked.
+fn sender(call: &EvaluatedCall) -> Result<Option<bool>, LabeledError> {
+    let Some(sender) = call.get_flag::<Spanned<String>>("sender")? else {
+        return Ok(None);
+    };
+    match sender.item.as_str() {
+        "client" => Ok(Some(true)),
+        "server" => Ok(Some(false)),
+        _ => Err(LabeledError::new("Invalid sender")
+            .with_label("expected client or server", sender.span)),
+    }
+}
+
+fn frame_from_value(value: &Value) -> Result<Frame, LabeledError> {
+    let invalid = |message: String, span: Span| {
+        LabeledError::new("Invalid frame record").with_label(message, span)
+    };
+    let record = value.as_record().map_err(|_| {
+        invalid(
+            "expected a record with an `opcode` and optional `payload`".into(),
+            value.span(),
+        )
+    })?;
+    if let Some(column) = record
+        .columns()
+        .find(|column| !FIELDS.contains(&column.as_str()))
+    {
+        return Err(invalid(
+            format!(
+                "unknown field `{column}`, expected one of {}",
+                FIELDS.join(", ")
+            ),
+            value.span(),
+        ));
+    }
+
+    let flag = |name: &str, default: bool| match record.get(name) {
+        None => Ok(default),
+        Some(value) => value
+            .as_bool()
+            .map_err(|_| invalid(format!("`{name}` must be a bool"), value.span())),
+    };
+
+    let opcode = record
+        .get("opcode")
+        .ok_or_else(|| invalid("the record has no `opcode`".into(), value.span()))?;
+    let opcode = match opcode {
+        Value::Int { val, .. } => u8::try_from(*val).ok().filter(|opcode| *opcode <= 0x0F),
+        Value::String { val, .. } => {
+            (0..=0x0F).find(|opcode| frame::opcode_name(OpCode::from(*opcode)) == Some(val))
+        }
+        _ => None,
+    }
+    .map(OpCode::from)
+    .ok_or_else(|| {
+        invalid(
+            "expected text, binary, continuation, close, ping, pong or a number from 0 to 15"
+                .into(),
+            opcode.span(),
+        )
+    })?;
+
+    let mask = match record.get("mask") {
+        None | Some(Value::Nothing { .. }) | Some(Value::Bool { val: false, .. }) => None,
+        Some(Value::Bool { val: true, .. }) => {
+            let mut key = [0; 4];
+            getrandom::getrandom(&mut key).expect("Could not get randomness from the OS");
+            Some(key)
+        }
+        Some(mask) => Some(
+            mask.as_binary()
+                .ok()
+                .and_then(|key| <[u8; 4]>::try_from(key).ok())
+                .ok_or_else(|| {
+                    invalid("expected a 4-byte binary key or a bool".into(), mask.span())
+                })?,
+        ),
+    };
+
+    let payload = match record.get("payload") {
+        None | Some(Value::Nothing { .. }) => vec![],
+        Some(Value::String { val, .. }) => val.clone().into_bytes(),
+        Some(Value::Binary { val, .. }) => val.clone(),
+        Some(payload) => {
+            return Err(invalid(
+                "`payload` must be a string or binary".into(),
+                payload.span(),
+            ))
+        }
+    };
+
+    let header = FrameHeader {
+        is_final: flag("fin", true)?,
+        rsv1: flag("rsv1", false)?,
+        rsv2: flag("rsv2", false)?,
+        rsv3: flag("rsv3", false)?,
+        opcode,
+        mask,
+    };
+    Ok(Frame::from_payload(header, payload))
+}
+
+fn frame_value(frame: Frame, span: Span) -> Value {
+    let header = frame.header().clone();
+    let mut record = Record::new();
+    record.push("fin", Value::bool(header.is_final, span));
+    record.push("rsv1", Value::bool(header.rsv1, span));
+    record.push("rsv2", Value::bool(header.rsv2, span));
+    record.push("rsv3", Value::bool(header.rsv3, span));
+    record.push("opcode", opcode_value(header.opcode, span));
+    record.push(
+        "mask",
+        header.mask.map_or(Value::nothing(span), |key| {
+            Value::binary(key.to_vec(), span)
+        }),
+    );
+    record.push(
+        "payload",
+        payload_value(header.opcode, frame.into_data(), span),
+    );
+    Value::record(record, span)
+}
+
+/// The name of a defined opcode, or its number.
+pub(crate) fn opcode_value(opcode: OpCode, span: Span) -> Value {
+    match frame::opcode_name(opcode) {
+        Some(name) => Value::string(name, span),
+        None => Value::int(u8::from(opcode).into(), span),
+    }
+}
+
+/// Text payloads as strings when they are valid UTF-8, anything else as binary.
+pub(crate) fn payload_value(opcode: OpCode, payload: Vec<u8>, span: Span) -> Value {
+    match (opcode, String::from_utf8(payload)) {
+        (OpCode::Data(Data::Text), Ok(text)) => Value::string(text, span),
+        (_, Ok(text)) => Value::binary(text.into_bytes(), span),
+        (_, Err(e)) => Value::binary(e.into_bytes(), span),
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, SyntaxShape, Type, Value,
+};
+use tungstenite::{
+    handshake::client::Request,
+    http::HeaderMap,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use super::{
+    connect_options, connection_flags, cookie_jar, handshake_headers, init_logging, save_cookies,
+    session_url,
+};
+use crate::{
+    ws::{
+        cert,
+        client::{build_request, set_read_timeout},
+        connector::Timings,
+    },
+    WebSocketPlugin,
+};
+
+/// How long to wait for the server to answer the close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct WebSocketHandshake;
+
+impl PluginCommand for WebSocketHandshake {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws handshake"
+    }
+
+    fn description(&self) -> &str {
+        "perform only the WebSocket upgrade and describe what was negotiated"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Builds the request like `ws` does, completes the upgrade and closes the connection \
+         right away with code 1000. Returns the request that was sent, the response status and \
+         headers, the negotiated subprotocol and extensions, a summary of the server's TLS \
+         certificate and how long DNS, TCP, TLS and the upgrade took. A response other than 101 \
+         is reported the same way instead of failing. Redirects are not followed."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::record())])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL to probe (ws:// or wss://).",
+                )
+                .named(
+                    "subprotocols",
+                    SyntaxShape::List(Box::new(SyntaxShape::String)),
+                    "subprotocols to offer, in order of preference",
+                    Some('s'),
+                )
+                .switch(
+                    "dry-run",
+                    "only build the request and return it, without connecting",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        init_logging(call)?;
+
+        let (url, span) = session_url(call)?;
+        let subprotocols: Vec<String> = call.get_flag("subprotocols")?.unwrap_or_default();
+        let head = call.head;
+
+        let (url, mut connector) = connect_options(call, engine, &url, span)?;
+        let jar = cookie_jar(call, engine)?;
+        let headers = handshake_headers(call, &url, jar.as_ref())?;
+        let request = build_request(&url, headers, &subprotocols).map_err(|e| {
+            LabeledError::new(format!("Invalid handshake request: {e}"))
+                .with_label("could not build a request for this URL", span)
+        })?;
+        let request_value = request_record(&request, head);
+
+        if call.has_flag("dry-run")? {
+            return Ok(PipelineData::Value(request_value, None));
+        }
+
+        connector.max_redirects = 0;
+        let start = Instant::now();
+        let result = connector.connect(request);
+        let total = start.elapsed();
+
+        let response = match result {
+            Ok((mut socket, response)) => {
+                save_cookies(jar, &url, &response);
+                log::debug!("Upgrade succeeded, closing the connection");
+                let _ = socket.close(Some(CloseFrame {
+                    code: CloseCode::Normal,
+                    reason: Cow::Borrowed(""),
+                }));
+                // Wait for the server to answer the close frame, but not for a server that
+                // keeps sending messages instead
+                let deadline = Instant::now() + CLOSE_TIMEOUT;
+                while let Some(remaining) = deadline
+                    .checked_duration_since(Instant::now())
+                    .filter(|remaining| !remaining.is_zero())
+                {
+                    let _ = set_read_timeout(&socket, Some(remaining));
+                    match socket.read() {
+                        Ok(Message::Close(_)) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                }
+                response
+            }
+            Err(tungstenite::Error::Http(response)) => response,
+            Err(e) => {
+                let error = LabeledError::new(format!("Handshake failed: {e}"))
+                    .with_label("could not connect to this URL", span);
+                return Err(match completed_steps(&connector.timings) {
+                    Some(steps) => error.with_help(format!("completed before failing: {steps}")),
+                    None => error,
+                });
+            }
+        };
+
+        let mut record = Record::new();
+        record.push("request", request_value);
+        record.push(
+            "status",
+            Value::int(response.status().as_u16().into(), head),
+        );
+        record.push("headers", headers_table(response.headers(), head));
+        record.push(
+            "subprotocol",
+            header_value(response.headers(), "Sec-WebSocket-Protocol")
+                .map_or(Value::nothing(head), |protocol| {
+                    Value::string(protocol, head)
+                }),
+        );
+        record.push(
+            "extensions",
+            Value::list(
+                header_value(response.headers(), "Sec-WebSocket-Extensions")
+                    .map(|extensions| {
+                        extensions
+                            .split(',')
+                            .map(|extension| Value::string(extension.trim(), head))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                head,
+            ),
+        );
+        record.push(
+            "remote_address",
+            match (&connector.unix_socket, connector.peer_addr) {
+                (Some(path), _) => Value::string(path.display().to_string(), head),
+                (None, Some(addr)) => Value::string(addr.to_string(), head),
+                (None, None) => Value::nothing(head),
+            },
+        );
+        record.push(
+            "tls",
+            connector
+                .peer_certificate
+                .as_deref()
+                .map_or(Value::nothing(head), |der| certificate_record(der, head)),
+        );
+        record.push("timing", timing_record(&connector.timings, total, head));
+
+        Ok(PipelineData::Value(Value::record(record, head), None))
+    }
+}
+
+fn request_record(request: &Request, span: Span) -> Value {
+    let mut record = Record::new();
+    record.push("method", Value::string(request.method().as_str(), span));
+    record.push("url", Value::string(request.uri().to_string(), span));
+    record.push("headers", headers_table(request.headers(), span));
+    Value::record(record, span)
+}
+
+fn headers_table(headers: &HeaderMap, span: Span) -> Value {
+    Value::list(
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let mut record = Record::new();
+                record.push("name", Value::string(name.as_str(), span));
+                record.push(
+                    "value",
+                    Value::string(String::from_utf8_lossy(value.as_bytes()), span),
+                );
+                Value::record(record, span)
+            })
+            .collect(),
+        span,
+    )
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn certificate_record(der: &[u8], span: Span) -> Value {
+    let Some(summary) = cert::summarize(der) else {
+        return Value::nothing(span);
+    };
+    let date = |date: Option<_>| date.map_or(Value::nothing(span), |date| Value::date(date, span));
+
+    let mut record = Record::new();
+    record.push("subject", Value::string(summary.subject, span));
+    record.push("issuer", Value::string(summary.issuer, span));
+    record.push(
+        "subject_alt_names",
+        Value::list(
+            summary
+                .subject_alt_names
+                .into_iter()
+                .map(|name| Value::string(name, span))
+                .collect(),
+            span,
+        ),
+    );
+    record.push("serial", Value::string(summary.serial, span));
+    record.push("not_before", date(summary.not_before));
+    record.push("not_after", date(summary.not_after));
+    record.push("sha256", Value::string(summary.sha256, span));
+    Value::record(record, span)
+}
+
+fn timing_record(timings: &Timings, total: Duration, span: Span) -> Value {
+    let duration = |duration: Option<Duration>| {
+        duration.map_or(Value::nothing(span), |duration| {
+            Value::duration(duration.as_nanos() as i64, span)
+        })
+    };
+
+    let mut record = Record::new();
+    record.push("dns", duration(timings.dns));
+    record.push("tcp", duration(timings.tcp));
+    record.push("tls", duration(timings.tls));
+    record.push("upgrade", duration(timings.upgrade));
+    record.push("total", duration(Some(total)));
+    Value::record(record, span)
+}
+
+fn completed_steps(timings: &Timings) -> Option<String> {
+    let steps: Vec<String> = [
+        ("DNS", timings.dns),
+        ("TCP", timings.tcp),
+        ("TLS", timings.tls),
+        ("upgrade", timings.upgrade),
+    ]
+    .into_iter()
+    .filter_map(|(step, duration)| duration.map(|duration| format!("{step} {duration:?}")))
+    .collect();
+
+    (!steps.is_empty()).then(|| steps.join(", "))
+}
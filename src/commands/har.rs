@@ -0,0 +1,293 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use tungstenite::Message;
+use url::Url;
+
+use super::{
+    check_ws_scheme, connection_flags, open_session_to, resolve_path, speed, stream_values,
+};
+use crate::{
+    ws::{
+        client::append_query_flag,
+        har::{self, HarConnection, HarFrame},
+        recording::Direction,
+        session::Received,
+    },
+    WebSocketPlugin,
+};
+
+pub struct WebSocketFromHar;
+
+impl PluginCommand for WebSocketFromHar {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws from-har"
+    }
+
+    fn description(&self) -> &str {
+        "extract the WebSocket connections captured in a HAR file"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Reads a HAR file exported from the browser developer tools and returns one \
+         {url, started, status, request_headers, frames} record per WebSocket connection. Each \
+         frame is {direction, time, opcode, payload}, where direction is sent or received from \
+         the browser's point of view."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(PluginCommand::name(self))
+            .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+            .required("file", SyntaxShape::Filepath, "The HAR file to read.")
+            .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let head = call.head;
+        let connections = load(call, engine)?;
+        Ok(PipelineData::Value(
+            Value::list(
+                connections
+                    .iter()
+                    .map(|connection| connection_record(connection, head))
+                    .collect(),
+                head,
+            ),
+            None,
+        ))
+    }
+}
+
+pub struct WebSocketReplayHar;
+
+impl PluginCommand for WebSocketReplayHar {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws replay-har"
+    }
+
+    fn description(&self) -> &str {
+        "replay what the browser sent on a WebSocket connection captured in a HAR file"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Connects to the captured URL, or to the given one, offering the captured subprotocols, \
+         and sends the messages the browser sent with their original timing, divided by \
+         --speed. Every frame sent and received is output as {direction, time, opcode, \
+         payload}. Receiving continues after the last message until the server closes the \
+         connection or --max-time passes. Captured headers are not sent; use --headers, \
+         --cookie or --bearer to authenticate."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+                .required("file", SyntaxShape::Filepath, "The HAR file to read.")
+                .optional(
+                    "URL",
+                    SyntaxShape::String,
+                    "Where to connect instead of the captured URL.",
+                )
+                .named(
+                    "index",
+                    SyntaxShape::Int,
+                    "which WebSocket connection of the file to replay, counting from 0 (default 0)",
+                    Some('i'),
+                )
+                .named(
+                    "speed",
+                    SyntaxShape::Any,
+                    "how much faster than captured to send, e.g. 10 or 10x (default 1)",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let head = call.head;
+        let file: Spanned<String> = call.req(0)?;
+        let mut connections = load(call, engine)?;
+
+        let index = call.get_flag::<Spanned<i64>>("index")?;
+        let position = index.as_ref().map_or(0, |index| index.item);
+        let connection = usize::try_from(position)
+            .ok()
+            .filter(|position| *position < connections.len())
+            .map(|position| connections.swap_remove(position))
+            .ok_or_else(|| {
+                LabeledError::new(format!(
+                    "No WebSocket connection {position} in the HAR file"
+                ))
+                .with_label(
+                    format!("the file has {} WebSocket connections", connections.len()),
+                    index.as_ref().map_or(file.span, |index| index.span),
+                )
+            })?;
+
+        let (mut url, span) = match call.opt::<Spanned<String>>(1)? {
+            Some(url) => (parse_url(&url.item, url.span)?, url.span),
+            None => (parse_url(&connection.url, file.span)?, file.span),
+        };
+        append_query_flag(call, &mut url)?;
+        let speed = speed(call)?;
+
+        let mut session = open_session_to(call, engine, &url, span, &connection.subprotocols())?;
+
+        // Messages are sent at their offset from the first captured frame
+        let origin = connection.frames.first().map_or(0.0, |frame| frame.time);
+        let mut sends = connection
+            .frames
+            .into_iter()
+            .filter(|frame| frame.direction == Direction::Sent)
+            .map(move |frame| {
+                // An offset too large for a Duration is never reached
+                let offset = Duration::try_from_secs_f64((frame.time - origin).max(0.0) / speed);
+                (offset.ok(), frame.message)
+            })
+            .peekable();
+        let start = Instant::now();
+
+        Ok(stream_values(
+            move || loop {
+                let next_send = sends
+                    .peek()
+                    .and_then(|(offset, _)| start.checked_add((*offset)?));
+                if next_send.is_some_and(|at| Instant::now() >= at) {
+                    let (_, message) = sends.next().expect("peeked");
+                    session.send(message.clone())?;
+                    return Ok(Some(frame_value(
+                        Direction::Sent,
+                        Utc::now(),
+                        message,
+                        head,
+                    )));
+                }
+                match session.recv_until(next_send)? {
+                    Received::Message(message) => {
+                        return Ok(Some(frame_value(
+                            Direction::Received,
+                            Utc::now(),
+                            message,
+                            head,
+                        )))
+                    }
+                    Received::Idle => continue,
+                    Received::Closed => return Ok(None),
+                }
+            },
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// Reads the HAR file in the first positional argument.
+fn load(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+) -> Result<Vec<HarConnection>, LabeledError> {
+    let file: Spanned<String> = call.req(0)?;
+    let path = resolve_path(engine, &file)?;
+    let invalid = |e: String| {
+        LabeledError::new(format!("Could not read the HAR file: {e}"))
+            .with_label("while reading this file", file.span)
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| invalid(e.to_string()))?;
+    let json = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+    har::connections(&json).map_err(invalid)
+}
+
+fn parse_url(url: &str, span: Span) -> Result<Url, LabeledError> {
+    let url = Url::parse(url).map_err(|e| {
+        LabeledError::new(format!("Invalid URL: {e}")).with_label("expected a ws:// URL", span)
+    })?;
+    check_ws_scheme(&url, span)?;
+    Ok(url)
+}
+
+fn connection_record(connection: &HarConnection, span: Span) -> Value {
+    let mut record = Record::new();
+    record.push("url", Value::string(&connection.url, span));
+    record.push(
+        "started",
+        connection
+            .started
+            .as_deref()
+            .and_then(|started| DateTime::parse_from_rfc3339(started).ok())
+            .map_or(Value::nothing(span), |started| Value::date(started, span)),
+    );
+    record.push(
+        "status",
+        connection.status.map_or(Value::nothing(span), |status| {
+            Value::int(status.into(), span)
+        }),
+    );
+    record.push(
+        "request_headers",
+        Value::list(
+            connection
+                .request_headers
+                .iter()
+                .map(|(name, value)| {
+                    let mut header = Record::new();
+                    header.push("name", Value::string(name, span));
+                    header.push("value", Value::string(value, span));
+                    Value::record(header, span)
+                })
+                .collect(),
+            span,
+        ),
+    );
+    record.push(
+        "frames",
+        Value::list(
+            connection
+                .frames
+                .iter()
+                .map(|frame| har_frame_value(frame, span))
+                .collect(),
+            span,
+        ),
+    );
+    Value::record(record, span)
+}
+
+fn har_frame_value(frame: &HarFrame, span: Span) -> Value {
+    let time = DateTime::from_timestamp_micros((frame.time * 1e6) as i64).unwrap_or_default();
+    frame_value(frame.direction, time, frame.message.clone(), span)
+}
+
+fn frame_value(direction: Direction, time: DateTime<Utc>, message: Message, span: Span) -> Value {
+    let (opcode, payload) = match message {
+        Message::Text(text) => ("text", Value::string(text, span)),
+        other => ("binary", Value::binary(other.into_data(), span)),
+    };
+    let mut record = Record::new();
+    record.push("direction", Value::string(direction.as_str(), span));
+    record.push("time", Value::date(time.fixed_offset(), span));
+    record.push("opcode", Value::string(opcode, span));
+    record.push("payload", payload);
+    Value::record(record, span)
+}
@@ -0,0 +1,117 @@
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{LabeledError, PipelineData, Record, Span, Value};
+use tungstenite::Message;
+
+use super::{open_session, stream_values};
+use crate::ws::{json::json_to_value, receipt::Receipts, session::Session};
+
+/// Channel protocols offered, newest first; v5 adds closing stdin with a close frame.
+const SUBPROTOCOLS: [&str; 2] = ["v5.channel.k8s.io", "v4.channel.k8s.io"];
+
+const STDIN: u8 = 0;
+const STDOUT: u8 = 1;
+const STDERR: u8 = 2;
+const ERROR: u8 = 3;
+const RESIZE: u8 = 4;
+/// v5 only: the payload is the channel being closed.
+const CLOSE: u8 = 255;
+
+/// Runs `ws --k8s-channels`: sends the input as stdin of an exec/attach session and streams
+/// what the container writes as `{stream, data}` records, with `seq` and `received` added
+/// by `--timestamps`.
+pub(crate) fn run_k8s_channels(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    input: PipelineData,
+) -> Result<PipelineData, LabeledError> {
+    let stdin = match input {
+        PipelineData::Empty | PipelineData::Value(Value::Nothing { .. }, ..) => None,
+        PipelineData::Value(Value::String { val, .. }, ..) => Some(val.into_bytes()),
+        PipelineData::Value(Value::Binary { val, .. }, ..) => Some(val),
+        PipelineData::ByteStream(stream, ..) => Some(stream.into_bytes()?),
+        other => {
+            return Err(LabeledError::new("Input must be string or binary")
+                .with_label("Unsupported input type", other.span().unwrap_or(call.head)))
+        }
+    };
+
+    let subprotocols = SUBPROTOCOLS.map(String::from);
+    let mut session = open_session(call, engine, &subprotocols)?;
+    let protocol = session.subprotocol().map(str::to_string);
+    log::debug!("Negotiated channel protocol: {protocol:?}");
+
+    if let Some(stdin) = stdin {
+        log::debug!("Sending {} bytes to stdin", stdin.len());
+        let mut frame = Vec::with_capacity(stdin.len() + 1);
+        frame.push(STDIN);
+        frame.extend(stdin);
+        session.send(Message::Binary(frame))?;
+
+        if protocol.as_deref() == Some(SUBPROTOCOLS[0]) {
+            log::debug!("Closing stdin");
+            session.send(Message::Binary(vec![CLOSE, STDIN]))?;
+        }
+    }
+
+    let mut receipts = call.has_flag("timestamps")?.then(Receipts::new);
+    let head = call.head;
+    Ok(stream_values(
+        move || next_record(&mut session, receipts.as_mut(), head),
+        head,
+        engine.signals().clone(),
+    ))
+}
+
+fn next_record(
+    session: &mut Session,
+    mut receipts: Option<&mut Receipts>,
+    span: Span,
+) -> Result<Option<Value>, LabeledError> {
+    while let Some(message) = session.recv()? {
+        let receipt = receipts.as_deref_mut().map(Receipts::stamp);
+        let Message::Binary(frame) = message else {
+            log::debug!("Ignoring non-binary message: {message:?}");
+            continue;
+        };
+        let Some((&channel, data)) = frame.split_first() else {
+            continue;
+        };
+        let stream = match channel {
+            STDOUT => "stdout",
+            STDERR => "stderr",
+            ERROR => "error",
+            // The first frame on each channel is empty and only announces it
+            STDIN | RESIZE | CLOSE => continue,
+            other => {
+                log::debug!("Ignoring frame on unknown channel {other}");
+                continue;
+            }
+        };
+        if data.is_empty() {
+            continue;
+        }
+
+        let data = match (channel, std::str::from_utf8(data)) {
+            // The error channel carries a v1.Status object describing how the process ended
+            (ERROR, Ok(text)) => match serde_json::from_str(text) {
+                Ok(status) => json_to_value(status, span),
+                Err(_) => Value::string(text, span),
+            },
+            (_, Ok(text)) => Value::string(text, span),
+            (_, Err(_)) => Value::binary(data, span),
+        };
+
+        let mut record = Record::new();
+        record.push("stream", Value::string(stream, span));
+        record.push("data", data);
+        if let Some(receipt) = receipt {
+            record.push("seq", Value::int(receipt.seq as i64, span));
+            record.push(
+                "received",
+                Value::date(receipt.received.fixed_offset(), span),
+            );
+        }
+        return Ok(Some(Value::record(record, span)));
+    }
+    Ok(None)
+}
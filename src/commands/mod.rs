@@ -0,0 +1,609 @@
+use std::{
+    net::{IpAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{
+    LabeledError, ListStream, PipelineData, ShellError, Signals, Signature, Span, Spanned,
+    SyntaxShape, Value,
+};
+use tungstenite::handshake::client::Response;
+use url::Url;
+
+use crate::ws::{
+    client::{
+        basic_auth, default_origin, http_parse_url, redact_url, request_headers, POLL_INTERVAL,
+    },
+    connector::{unix_socket_url, Connector, IpFamily, ResolveOverride},
+    cookies::CookieJar,
+    framing::Framing,
+    headers::Headers,
+    server::StopOnDrop,
+    session::Session,
+};
+
+pub mod actioncable;
+pub mod cdp;
+pub mod frame;
+pub mod handshake;
+pub mod har;
+pub mod k8s;
+pub mod nostr;
+pub mod ocpp;
+pub mod pcap;
+pub mod phoenix;
+pub mod pusher;
+pub mod replay;
+pub mod rpc;
+pub mod signalr;
+pub mod wamp;
+pub mod xmpp;
+
+/// Adds the flags every command that opens a connection understands.
+pub(crate) fn connection_flags(signature: Signature) -> Signature {
+    signature
+        .named(
+            "headers",
+            SyntaxShape::Any,
+            "custom headers you want to add ",
+            Some('H'),
+        )
+        .named(
+            "query",
+            SyntaxShape::Record(vec![]),
+            "query parameters to add to the URL, e.g. {token: abc, streams: [a b]}",
+            Some('q'),
+        )
+        .named(
+            "user",
+            SyntaxShape::Any,
+            "the username when authenticating",
+            Some('u'),
+        )
+        .named(
+            "password",
+            SyntaxShape::Any,
+            "the password when authenticating",
+            None,
+        )
+        .named(
+            "bearer",
+            SyntaxShape::String,
+            "a token to send as `Authorization: Bearer <token>`",
+            None,
+        )
+        .named(
+            "cookie",
+            SyntaxShape::Record(vec![]),
+            "cookies to send with the handshake, e.g. {session: abc123}",
+            None,
+        )
+        .named(
+            "cookie-jar",
+            SyntaxShape::Filepath,
+            "a Netscape (curl) format cookie file to send cookies from and save new ones to",
+            None,
+        )
+        .named(
+            "origin",
+            SyntaxShape::String,
+            "the Origin header to send (default: the http(s) origin of the URL)",
+            None,
+        )
+        .switch("no-origin", "do not send an Origin header", None)
+        .named(
+            "user-agent",
+            SyntaxShape::String,
+            "the User-Agent header to send",
+            None,
+        )
+        .named(
+            "unix-socket",
+            SyntaxShape::Filepath,
+            "connect through this Unix socket instead of TCP",
+            None,
+        )
+        .named(
+            "resolve",
+            SyntaxShape::Any,
+            "use these addresses for a host, as host:port:address[,address] (a string or a list)",
+            None,
+        )
+        .switch("ipv4", "only connect over IPv4", None)
+        .switch("ipv6", "only connect over IPv6", None)
+        .named(
+            "local-addr",
+            SyntaxShape::String,
+            "bind outgoing connections to this local IP address",
+            None,
+        )
+        .named(
+            "max-time",
+            SyntaxShape::Duration,
+            "max duration before timeout occurs",
+            Some('m'),
+        )
+        .named(
+            "verbose",
+            SyntaxShape::Int,
+            "verbosity level (0=error, 1=warn, 2=info, 3=debug, 4=trace)",
+            Some('v'),
+        )
+}
+
+/// Sets up logging from the `--verbose` flag.
+#[allow(clippy::result_large_err)]
+pub(crate) fn init_logging(call: &EvaluatedCall) -> Result<(), ShellError> {
+    let verbose: Option<Value> = call.get_flag("verbose")?;
+
+    // Set up logging based on verbose level
+    let log_level_filter = if let Some(Value::Int { val, .. }) = verbose {
+        match val {
+            0 => log::LevelFilter::Error,
+            1 => log::LevelFilter::Warn,
+            2 => log::LevelFilter::Info,
+            3 => log::LevelFilter::Debug,
+            4 => log::LevelFilter::Trace,
+            _ => log::LevelFilter::Info,
+        }
+    } else {
+        log::LevelFilter::Error // Default to error only
+    };
+
+    // Initialize env_logger with the specified level (only if not already initialized).
+    // tungstenite traces the raw upgrade request, credentials included, so it stops at debug.
+    let _ = env_logger::Builder::from_default_env()
+        .filter_level(log_level_filter)
+        .filter_module("tungstenite", log_level_filter.min(log::LevelFilter::Debug))
+        .try_init();
+
+    Ok(())
+}
+
+/// Reads the `--max-time` flag.
+#[allow(clippy::result_large_err)]
+pub(crate) fn max_time(call: &EvaluatedCall) -> Result<Option<Duration>, ShellError> {
+    let timeout: Option<Value> = call.get_flag("max-time")?;
+    timeout
+        .map(|val| {
+            let duration = Duration::from_nanos(val.as_duration()?.max(0) as u64);
+            log::trace!("Setting timeout to: {duration:?}");
+            Ok(duration)
+        })
+        .transpose()
+}
+
+/// Adds the `--port` and `--bind` flags of the plugin's local servers.
+pub(crate) fn server_flags(signature: Signature, default_port: u16) -> Signature {
+    signature
+        .named(
+            "port",
+            SyntaxShape::Int,
+            format!("port to listen on (default {default_port})"),
+            Some('p'),
+        )
+        .named(
+            "bind",
+            SyntaxShape::String,
+            "address to listen on (default 127.0.0.1)",
+            Some('b'),
+        )
+}
+
+/// Listens on the address from `--bind` and `--port`, without blocking on accept.
+pub(crate) fn listen(call: &EvaluatedCall, default_port: u16) -> Result<TcpListener, LabeledError> {
+    let port = match call.get_flag::<Spanned<i64>>("port")? {
+        Some(port) => u16::try_from(port.item).map_err(|_| {
+            LabeledError::new("Invalid port").with_label("expected 0 to 65535", port.span)
+        })?,
+        None => default_port,
+    };
+    let bind = call
+        .get_flag::<String>("bind")?
+        .unwrap_or_else(|| "127.0.0.1".into());
+
+    let listener = TcpListener::bind((bind.as_str(), port)).map_err(|e| {
+        LabeledError::new(format!("Could not listen on {bind}:{port}: {e}"))
+            .with_label("while starting this server", call.head)
+    })?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| LabeledError::new(format!("Failed to configure socket: {e}")))?;
+    Ok(listener)
+}
+
+/// Streams the records a local server sends on `rx` until `--max-time` passes or the output
+/// is dropped, which stops the server through `stop`.
+pub(crate) fn serve_values(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    rx: Receiver<Value>,
+    stop: StopOnDrop,
+) -> Result<PipelineData, LabeledError> {
+    let head = call.head;
+    let deadline = max_time(call)?.map(|timeout| Instant::now() + timeout);
+    let signals = engine.signals().clone();
+    Ok(stream_values(
+        move || {
+            let _serving = &stop;
+            loop {
+                signals.check(&head)?;
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Ok(None);
+                }
+                match rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(record) => return Ok(Some(record)),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                }
+            }
+        },
+        head,
+        engine.signals().clone(),
+    ))
+}
+
+/// Reads the `--framing` flag of `ws`.
+pub(crate) fn framing(call: &EvaluatedCall) -> Result<Framing, LabeledError> {
+    let Some(framing) = call.get_flag::<Spanned<String>>("framing")? else {
+        return Ok(Framing::default());
+    };
+    framing
+        .item
+        .parse()
+        .map_err(|e: String| LabeledError::new("Invalid framing").with_label(e, framing.span))
+}
+
+/// The slowest and fastest `--speed`, which keep scaled delays within what a `Duration` holds.
+const SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.001..=1000.0;
+
+/// Reads `--speed`, a factor given as a number or as a string like `10x`.
+pub(crate) fn speed(call: &EvaluatedCall) -> Result<f64, LabeledError> {
+    let Some(value) = call.get_flag::<Value>("speed")? else {
+        return Ok(1.0);
+    };
+    let span = value.span();
+    let speed = match &value {
+        Value::Int { val, .. } => Some(*val as f64),
+        Value::Float { val, .. } => Some(*val),
+        Value::String { val, .. } => val.trim_end_matches('x').parse().ok(),
+        _ => None,
+    };
+    speed
+        .filter(|speed| SPEED_RANGE.contains(speed))
+        .ok_or_else(|| {
+            LabeledError::new("Invalid speed")
+                .with_label("expected a factor like 2, 0.5 or 10x", span)
+                .with_help(format!(
+                    "the speed must be from {} to {}",
+                    SPEED_RANGE.start(),
+                    SPEED_RANGE.end()
+                ))
+        })
+}
+
+/// Loads the `--cookie-jar` file, if one was given.
+pub(crate) fn cookie_jar(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+) -> Result<Option<CookieJar>, LabeledError> {
+    let Some(path) = call.get_flag::<Spanned<String>>("cookie-jar")? else {
+        return Ok(None);
+    };
+    CookieJar::load(&resolve_path(engine, &path)?)
+        .map(Some)
+        .map_err(|e| {
+            LabeledError::new(format!("Failed to read cookie jar: {e}"))
+                .with_label("could not read this cookie file", path.span)
+        })
+}
+
+/// Saves the cookies set by the handshake response back to the cookie jar.
+pub(crate) fn save_cookies(jar: Option<CookieJar>, url: &Url, response: &Response) {
+    let Some(mut jar) = jar else {
+        return;
+    };
+    let set_cookies = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok());
+    if jar.store(url, set_cookies) {
+        if let Err(e) = jar.save() {
+            log::warn!("Could not save cookie jar: {e}");
+        }
+    }
+}
+
+/// Reads `--headers` together with the authentication and cookie flags, for a handshake
+/// with `url`.
+///
+/// An explicit `Authorization` header takes precedence over `--bearer` and `--user`, which
+/// in turn take precedence over credentials embedded in the URL. Cookies from `--cookie`
+/// replace those of the same name from the jar, and are appended to the last `Cookie` header.
+/// Headers given with `--headers` replace the default `Origin` and `User-Agent`.
+pub(crate) fn handshake_headers(
+    call: &EvaluatedCall,
+    url: &Url,
+    jar: Option<&CookieJar>,
+) -> Result<Headers, LabeledError> {
+    let mut headers = request_headers(call.get_flag("headers")?)?;
+
+    let origin: Option<Spanned<String>> = call.get_flag("origin")?;
+    let no_origin = call.has_flag("no-origin")?;
+    let user_agent: Option<String> = call.get_flag("user-agent")?;
+    if let (Some(origin), true) = (&origin, no_origin) {
+        return Err(LabeledError::new("Conflicting Origin flags")
+            .with_label("--origin cannot be combined with --no-origin", origin.span));
+    }
+    if !no_origin && !headers.contains("Origin") {
+        let origin = origin.map_or_else(|| default_origin(url), |origin| origin.item);
+        headers.append("Origin", origin);
+    }
+    if !headers.contains("User-Agent") {
+        let user_agent =
+            user_agent.unwrap_or_else(|| format!("nu_plugin_ws/{}", env!("CARGO_PKG_VERSION")));
+        headers.append("User-Agent", user_agent);
+    }
+
+    let mut cookies: Vec<(String, String)> = jar
+        .map(|jar| jar.matching(url))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|cookie| (cookie.name.clone(), cookie.value.clone()))
+        .collect();
+    if let Some(Value::Record { val, .. }) = call.get_flag("cookie")? {
+        for (name, value) in val.into_owned() {
+            cookies.retain(|(existing, _)| *existing != name);
+            cookies.push((name, value.coerce_into_string()?));
+        }
+    }
+    if !cookies.is_empty() {
+        let cookie = cookies
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        match headers.last_mut("Cookie") {
+            Some(existing) => {
+                existing.push_str("; ");
+                existing.push_str(&cookie);
+            }
+            None => headers.append("Cookie", cookie),
+        }
+    }
+    let bearer: Option<Spanned<String>> = call.get_flag("bearer")?;
+    let user: Option<Value> = call.get_flag("user")?;
+    let password: Option<Value> = call.get_flag("password")?;
+
+    if headers.contains("Authorization") {
+        return Ok(headers);
+    }
+
+    match (bearer, user) {
+        (Some(bearer), Some(_)) => {
+            return Err(LabeledError::new("Conflicting credentials")
+                .with_label("--bearer cannot be combined with --user", bearer.span));
+        }
+        (Some(bearer), None) => {
+            headers.append("Authorization", format!("Bearer {}", bearer.item));
+        }
+        (None, Some(user)) => {
+            let password = match password {
+                Some(password) => password.coerce_into_string()?,
+                None => String::new(),
+            };
+            headers.append(
+                "Authorization",
+                basic_auth(&user.coerce_into_string()?, &password),
+            );
+        }
+        (None, None) => {}
+    }
+
+    Ok(headers)
+}
+
+/// Parses the URL in the first positional argument, which must be a ws:// or wss:// URL.
+pub(crate) fn session_url(call: &EvaluatedCall) -> Result<(Url, Span), LabeledError> {
+    let url: Value = call.req(0)?;
+    let span = url.span();
+    let (_, requested_url) = http_parse_url(call, span, url)?;
+    check_ws_scheme(&requested_url, span)?;
+
+    Ok((requested_url, span))
+}
+
+/// Fails unless `url` is one the WebSocket commands can connect to.
+pub(crate) fn check_ws_scheme(url: &Url, span: Span) -> Result<(), LabeledError> {
+    if !["ws", "wss", "ws+unix"].contains(&url.scheme()) {
+        return Err(LabeledError::new("Unsupported URL scheme")
+            .with_label("expected a ws://, wss:// or ws+unix:// URL", span));
+    }
+    Ok(())
+}
+
+/// Works out how to reach `url`. A `ws+unix://` URL or `--unix-socket` connects through a
+/// Unix socket instead of TCP.
+///
+/// Returns the `ws://` or `wss://` URL to request, along with the connector to use.
+pub(crate) fn connect_options(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    url: &Url,
+    span: Span,
+) -> Result<(Url, Connector), LabeledError> {
+    let mut connector = Connector {
+        resolve: resolve_overrides(call)?,
+        family: ip_family(call)?,
+        local_addr: local_addr(call)?,
+        deadline: max_time(call)?.map(|timeout| Instant::now() + timeout),
+        ..Connector::default()
+    };
+    let unix_socket: Option<Spanned<String>> = call.get_flag("unix-socket")?;
+
+    if let Some((socket, request_url)) = unix_socket_url(url) {
+        if let Some(flag) = unix_socket {
+            return Err(LabeledError::new("Conflicting Unix sockets")
+                .with_label("the URL already names a Unix socket", span)
+                .with_label("so --unix-socket cannot be used", flag.span));
+        }
+        log::debug!("Using Unix socket {} from the URL", socket.display());
+        connector.unix_socket = Some(socket);
+        return Ok((request_url, connector));
+    }
+
+    if let Some(path) = unix_socket {
+        if url.scheme() == "wss" {
+            return Err(LabeledError::new("TLS over Unix sockets is not supported")
+                .with_label("use a ws:// URL with --unix-socket", span));
+        }
+        connector.unix_socket = Some(resolve_path(engine, &path)?);
+    }
+
+    Ok((url.clone(), connector))
+}
+
+/// Parses `--resolve`, which takes a single override or a list of them.
+fn resolve_overrides(call: &EvaluatedCall) -> Result<Vec<ResolveOverride>, LabeledError> {
+    let Some(value) = call.get_flag::<Value>("resolve")? else {
+        return Ok(vec![]);
+    };
+    let values = match value {
+        Value::List { vals, .. } => vals,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            let span = value.span();
+            let entry = value.coerce_into_string().map_err(|_| {
+                LabeledError::new("Invalid --resolve entry")
+                    .with_label("expected a string like host:port:address", span)
+            })?;
+            entry.parse().map_err(|e: String| {
+                LabeledError::new("Invalid --resolve entry")
+                    .with_label(e, span)
+                    .with_help("use host:port:address[,address], like example.com:443:127.0.0.1")
+            })
+        })
+        .collect()
+}
+
+/// Reads `--ipv4` and `--ipv6`, which cannot both be given.
+fn ip_family(call: &EvaluatedCall) -> Result<Option<IpFamily>, LabeledError> {
+    match (call.has_flag("ipv4")?, call.has_flag("ipv6")?) {
+        (true, true) => Err(LabeledError::new("Conflicting address families")
+            .with_label("--ipv4 and --ipv6 cannot be used together", call.head)),
+        (true, false) => Ok(Some(IpFamily::V4)),
+        (false, true) => Ok(Some(IpFamily::V6)),
+        (false, false) => Ok(None),
+    }
+}
+
+fn local_addr(call: &EvaluatedCall) -> Result<Option<IpAddr>, LabeledError> {
+    let Some(addr) = call.get_flag::<Spanned<String>>("local-addr")? else {
+        return Ok(None);
+    };
+    addr.item.parse().map(Some).map_err(|_| {
+        LabeledError::new("Invalid local address")
+            .with_label("expected an IP address like 192.0.2.1 or ::1", addr.span)
+    })
+}
+
+/// Opens a [`Session`] to the URL in the first positional argument, using the shared
+/// connection flags.
+pub(crate) fn open_session(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    subprotocols: &[String],
+) -> Result<Session, LabeledError> {
+    let (url, span) = session_url(call)?;
+    open_session_to(call, engine, &url, span, subprotocols)
+}
+
+/// Opens a [`Session`] to `url`, using the shared connection flags.
+pub(crate) fn open_session_to(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    url: &Url,
+    span: Span,
+    subprotocols: &[String],
+) -> Result<Session, LabeledError> {
+    init_logging(call)?;
+
+    log::debug!("Connecting to: {}", redact_url(url));
+
+    let (url, connector) = connect_options(call, engine, url, span)?;
+    let jar = cookie_jar(call, engine)?;
+    let session = Session::open(
+        &url,
+        max_time(call)?,
+        handshake_headers(call, &url, jar.as_ref())?,
+        subprotocols,
+        connector,
+        engine.signals().clone(),
+        span,
+    )?;
+    save_cookies(jar, &url, session.response());
+
+    Ok(session)
+}
+
+/// Collects pipeline input into individual values, one per message to send.
+pub(crate) fn input_values(input: PipelineData) -> Result<Vec<Value>, LabeledError> {
+    Ok(match input {
+        PipelineData::Empty => vec![],
+        PipelineData::Value(Value::List { vals, .. }, ..) => vals,
+        PipelineData::Value(Value::Nothing { .. }, ..) => vec![],
+        PipelineData::Value(val, ..) => vec![val],
+        PipelineData::ListStream(stream, ..) => stream.into_iter().collect(),
+        PipelineData::ByteStream(stream, ..) => {
+            let span = stream.span();
+            vec![Value::string(stream.into_string()?, span)]
+        }
+    })
+}
+
+/// Streams the values produced by `next` until it returns `None` or fails.
+///
+/// A failure is reported as an error value at the end of the stream.
+pub(crate) fn stream_values(
+    mut next: impl FnMut() -> Result<Option<Value>, LabeledError> + Send + 'static,
+    span: Span,
+    signals: Signals,
+) -> PipelineData {
+    let mut done = false;
+    let values = std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        match next() {
+            Ok(Some(value)) => Some(value),
+            Ok(None) => {
+                done = true;
+                None
+            }
+            Err(e) => {
+                done = true;
+                Some(Value::error(ShellError::from(e), span))
+            }
+        }
+    });
+    PipelineData::ListStream(ListStream::new(values, span, signals), None)
+}
+
+/// Resolves a path argument against the shell's current directory rather than the plugin's.
+pub(crate) fn resolve_path(
+    engine: &EngineInterface,
+    path: &Spanned<String>,
+) -> Result<PathBuf, LabeledError> {
+    let path = Path::new(&path.item);
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(PathBuf::from(engine.get_current_dir()?).join(path))
+}
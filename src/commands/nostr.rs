@@ -0,0 +1,333 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use k256::schnorr::{Signature, SigningKey, VerifyingKey};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature as CommandSignature, Span, Spanned,
+    SyntaxShape, Type, Value,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::{connection_flags, input_values, open_session, resolve_path, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::Session,
+    },
+    WebSocketPlugin,
+};
+
+pub struct WebSocketNostr;
+
+impl PluginCommand for WebSocketNostr {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws nostr"
+    }
+
+    fn description(&self) -> &str {
+        "subscribe to events on a Nostr relay, or publish signed events to it"
+    }
+
+    fn extra_description(&self) -> &str {
+        "With --filter, a REQ is sent and matching events are streamed as records. Records piped \
+         in are signed with the hex secret key in --key-file and published instead; the relay's \
+         OK replies are returned. Fields missing from a piped record default to kind 1, no tags \
+         and the current time."
+    }
+
+    fn signature(&self) -> CommandSignature {
+        connection_flags(
+            CommandSignature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::list(Type::record())),
+                    (Type::record(), Type::list(Type::record())),
+                    (Type::list(Type::record()), Type::list(Type::record())),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL of the relay (ws:// or wss://).",
+                )
+                .named(
+                    "filter",
+                    SyntaxShape::Any,
+                    "a filter record, or a list of them, e.g. {kinds: [1], limit: 10}",
+                    Some('f'),
+                )
+                .switch(
+                    "until-eose",
+                    "stop once the relay signals the end of stored events",
+                    Some('e'),
+                )
+                .switch(
+                    "verify",
+                    "drop events whose id or signature does not check out",
+                    None,
+                )
+                .named(
+                    "key-file",
+                    SyntaxShape::Filepath,
+                    "file holding the hex secret key used to sign published events",
+                    Some('k'),
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let filter: Option<Value> = call.get_flag("filter")?;
+        let key_file: Option<Spanned<String>> = call.get_flag("key-file")?;
+        let until_eose = call.has_flag("until-eose")?;
+        let verify = call.has_flag("verify")?;
+        let head = call.head;
+
+        let drafts = input_values(input)?;
+        if !drafts.is_empty() {
+            let key_file = key_file.ok_or_else(|| {
+                LabeledError::new("Missing --key-file")
+                    .with_label("a secret key is needed to publish events", head)
+            })?;
+            let key = read_key(engine, &key_file)?;
+            let events = drafts
+                .iter()
+                .map(|draft| sign_event(&key, draft))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut relay = Relay::new(open_session(call, engine, &[])?);
+            let replies = events
+                .into_iter()
+                .map(|event| relay.publish(event, head))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(PipelineData::Value(Value::list(replies, head), None));
+        }
+
+        let Some(filter) = filter else {
+            return Err(LabeledError::new("Nothing to do").with_label(
+                "pass --filter to subscribe or pipe in events to publish",
+                head,
+            ));
+        };
+        let filters = match value_to_json(&filter)? {
+            serde_json::Value::Array(filters) => filters,
+            filter => vec![filter],
+        };
+
+        let mut relay = Relay::new(open_session(call, engine, &[])?);
+        relay.subscribe(filters)?;
+
+        Ok(stream_values(
+            move || {
+                Ok(relay
+                    .next_event(until_eose, verify)?
+                    .map(|event| json_to_value(event, head)))
+            },
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+struct Relay {
+    session: Session,
+    subscription: String,
+}
+
+impl Relay {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            subscription: random_hex::<8>(),
+        }
+    }
+
+    fn subscribe(&mut self, filters: Vec<serde_json::Value>) -> Result<(), LabeledError> {
+        let mut req = vec![json!("REQ"), json!(self.subscription)];
+        req.extend(filters);
+        log::debug!("Subscribing as {}", self.subscription);
+        self.session.send_json(&serde_json::Value::Array(req))
+    }
+
+    /// The next event of the subscription, or `None` once it is over.
+    fn next_event(
+        &mut self,
+        until_eose: bool,
+        verify: bool,
+    ) -> Result<Option<serde_json::Value>, LabeledError> {
+        while let Some(message) = self.session.recv_json()? {
+            let serde_json::Value::Array(mut message) = message else {
+                log::debug!("Ignoring non-array message: {message}");
+                continue;
+            };
+            let for_us = message.get(1).and_then(|s| s.as_str()) == Some(&self.subscription);
+            match message.first().and_then(|t| t.as_str()) {
+                Some("EVENT") if for_us && message.len() >= 3 => {
+                    let event = message.swap_remove(2);
+                    if verify {
+                        if let Err(reason) = verify_event(&event) {
+                            log::warn!("Dropping event {}: {reason}", event["id"]);
+                            continue;
+                        }
+                    }
+                    return Ok(Some(event));
+                }
+                Some("EOSE") if for_us => {
+                    log::debug!("End of stored events");
+                    if until_eose {
+                        self.close();
+                        return Ok(None);
+                    }
+                }
+                Some("CLOSED") if for_us => {
+                    let reason = message.get(2).and_then(|r| r.as_str()).unwrap_or_default();
+                    if reason.is_empty() {
+                        return Ok(None);
+                    }
+                    return Err(LabeledError::new(format!("Subscription closed: {reason}"))
+                        .with_label("the relay ended the subscription", self.session.span()));
+                }
+                Some("NOTICE") => {
+                    log::warn!("Relay notice: {}", message.get(1).unwrap_or(&json!(null)));
+                }
+                _ => log::trace!("Ignoring message: {message:?}"),
+            }
+        }
+        Ok(None)
+    }
+
+    fn close(&mut self) {
+        let close = json!(["CLOSE", self.subscription]);
+        if let Err(e) = self.session.send_json(&close) {
+            log::debug!("Could not close subscription: {e}");
+        }
+    }
+
+    /// Publishes `event` and waits for the relay to accept or reject it.
+    fn publish(&mut self, event: serde_json::Value, span: Span) -> Result<Value, LabeledError> {
+        let id = event["id"].clone();
+        log::debug!("Publishing event {id}");
+        self.session.send_json(&json!(["EVENT", event]))?;
+
+        while let Some(message) = self.session.recv_json()? {
+            match message[0].as_str() {
+                Some("OK") if message[1] == id => {
+                    let mut record = Record::new();
+                    record.push("id", json_to_value(id, span));
+                    record.push(
+                        "accepted",
+                        Value::bool(message[2].as_bool().unwrap_or_default(), span),
+                    );
+                    record.push(
+                        "message",
+                        Value::string(message[3].as_str().unwrap_or_default(), span),
+                    );
+                    return Ok(Value::record(record, span));
+                }
+                Some("NOTICE") => log::warn!("Relay notice: {}", message[1]),
+                _ => log::trace!("Ignoring message: {message}"),
+            }
+        }
+        Err(LabeledError::new("No reply to published event")
+            .with_label("the relay did not acknowledge this event", span))
+    }
+}
+
+fn read_key(engine: &EngineInterface, path: &Spanned<String>) -> Result<SigningKey, LabeledError> {
+    let contents = std::fs::read_to_string(resolve_path(engine, path)?).map_err(|e| {
+        LabeledError::new(format!("Could not read key file: {e}"))
+            .with_label("could not read this file", path.span)
+    })?;
+    hex::decode(contents.trim())
+        .ok()
+        .and_then(|bytes| SigningKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| {
+            LabeledError::new("Invalid secret key")
+                .with_label("expected 64 hex characters", path.span)
+        })
+}
+
+/// The NIP-01 id: the sha256 of the serialized `[0, pubkey, created_at, kind, tags, content]`.
+fn event_id(event: &serde_json::Value) -> [u8; 32] {
+    let serialized = json!([
+        0,
+        event["pubkey"],
+        event["created_at"],
+        event["kind"],
+        event["tags"],
+        event["content"],
+    ]);
+    Sha256::digest(serialized.to_string().as_bytes()).into()
+}
+
+fn verify_event(event: &serde_json::Value) -> Result<(), &'static str> {
+    let id = event_id(event);
+    if event["id"].as_str() != Some(&hex::encode(id)) {
+        return Err("id does not match its contents");
+    }
+    let pubkey = event["pubkey"]
+        .as_str()
+        .and_then(|key| hex::decode(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or("invalid pubkey")?;
+    let signature = event["sig"]
+        .as_str()
+        .and_then(|sig| hex::decode(sig).ok())
+        .and_then(|sig| Signature::try_from(sig.as_slice()).ok())
+        .ok_or("invalid signature encoding")?;
+    pubkey
+        .verify_raw(&id, &signature)
+        .map_err(|_| "signature does not verify")
+}
+
+fn sign_event(key: &SigningKey, draft: &Value) -> Result<serde_json::Value, LabeledError> {
+    let invalid = |msg: &str| LabeledError::new("Invalid event").with_label(msg, draft.span());
+    let draft = value_to_json(draft)?;
+    if !draft.is_object() {
+        return Err(invalid("expected a record with at least a `content` field"));
+    }
+    let content = draft["content"]
+        .as_str()
+        .ok_or_else(|| invalid("`content` must be a string"))?;
+    let created_at = match &draft["created_at"] {
+        serde_json::Value::Null => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        created_at => created_at
+            .as_u64()
+            .ok_or_else(|| invalid("`created_at` must be a unix timestamp"))?,
+    };
+
+    let mut event = json!({
+        "pubkey": hex::encode(key.verifying_key().to_bytes()),
+        "created_at": created_at,
+        "kind": draft.get("kind").cloned().unwrap_or(json!(1)),
+        "tags": draft.get("tags").cloned().unwrap_or(json!([])),
+        "content": content,
+    });
+    let id = event_id(&event);
+    let signature = key
+        .sign_raw(&id, &random_bytes())
+        .map_err(|e| LabeledError::new(format!("Could not sign event: {e}")))?;
+    event["id"] = json!(hex::encode(id));
+    event["sig"] = json!(hex::encode(signature.to_bytes()));
+    Ok(event)
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("Could not get randomness from the OS");
+    bytes
+}
+
+fn random_hex<const N: usize>() -> String {
+    hex::encode(random_bytes::<N>())
+}
@@ -0,0 +1,676 @@
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+};
+
+use chrono::{SecondsFormat, Utc};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    engine::Closure, Category, LabeledError, PipelineData, Record, Signature, Span, Spanned,
+    SyntaxShape, Type, Value,
+};
+use serde_json::json;
+use tungstenite::Message;
+
+use super::{
+    connection_flags, init_logging, input_values, listen, open_session, resolve_path, serve_values,
+    server_flags,
+};
+use crate::{
+    ws::{
+        client::is_read_timeout,
+        json::{json_to_value, value_to_json},
+        server::{accept, spawn_accept_loop, Stop},
+        session::Session,
+    },
+    WebSocketPlugin,
+};
+
+mod schema;
+
+use schema::{Direction, Schemas};
+
+/// Protocol versions spoken, newest first.
+const SUBPROTOCOLS: [&str; 2] = ["ocpp2.0.1", "ocpp1.6"];
+
+const CALL: u64 = 2;
+const CALL_RESULT: u64 = 3;
+const CALL_ERROR: u64 = 4;
+
+const DEFAULT_PORT: u16 = 9000;
+
+/// Seconds between heartbeats requested from charge points by the default BootNotification reply.
+const DEFAULT_HEARTBEAT_INTERVAL: u64 = 300;
+
+pub struct WebSocketOcppCall;
+
+impl PluginCommand for WebSocketOcppCall {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws ocpp call"
+    }
+
+    fn description(&self) -> &str {
+        "act as an OCPP charge point: send a call to a central system and return its result"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The URL is the central system endpoint followed by the charge point identity, e.g. \
+         ws://csms.example/ocpp/CP001. Without an action, a list of {action, payload} records \
+         is read from the pipeline and called in order on the same connection. A CALLERROR \
+         reply is reported as an error. Calls the central system makes in the meantime are \
+         answered with NotImplemented."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::record()),
+                    (Type::list(Type::record()), Type::list(Type::record())),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The central system URL, ending in the charge point identity.",
+                )
+                .optional(
+                    "action",
+                    SyntaxShape::String,
+                    "The action to call, e.g. BootNotification.",
+                )
+                .optional(
+                    "payload",
+                    SyntaxShape::Record(vec![]),
+                    "The payload of the call.",
+                )
+                .named(
+                    "ocpp-version",
+                    SyntaxShape::String,
+                    "only offer this protocol version (1.6 or 2.0.1)",
+                    None,
+                )
+                .named(
+                    "schemas",
+                    SyntaxShape::Directory,
+                    "validate payloads against the OCPP JSON schemas in this directory",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let action: Option<Spanned<String>> = call.opt(1)?;
+        let payload: Option<Value> = call.opt(2)?;
+        let version: Option<Spanned<String>> = call.get_flag("ocpp-version")?;
+        let schemas = schemas_flag(call, engine)?;
+
+        let single = action.is_some();
+        let calls = match action {
+            Some(action) => vec![(
+                action,
+                payload
+                    .as_ref()
+                    .map(value_to_json)
+                    .transpose()?
+                    .unwrap_or_else(|| json!({})),
+            )],
+            None => {
+                let calls = input_values(input)?
+                    .iter()
+                    .map(call_entry)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if calls.is_empty() {
+                    return Err(LabeledError::new("Missing action")
+                        .with_label("an action is required", call.head)
+                        .with_help("pipe in a list of {action, payload} records to send several"));
+                }
+                calls
+            }
+        };
+
+        let subprotocols = match version {
+            Some(version) => match version.item.as_str() {
+                "1.6" | "2.0.1" => vec![format!("ocpp{}", version.item)],
+                _ => {
+                    return Err(LabeledError::new("Unsupported OCPP version")
+                        .with_label("expected 1.6 or 2.0.1", version.span))
+                }
+            },
+            None => SUBPROTOCOLS.map(String::from).to_vec(),
+        };
+
+        let mut charge_point = ChargePoint::new(open_session(call, engine, &subprotocols)?);
+        match charge_point.session.subprotocol() {
+            Some(protocol) => log::debug!("Negotiated {protocol}"),
+            None => log::warn!("The central system did not select an OCPP version"),
+        }
+
+        let head = call.head;
+        let mut results = vec![];
+        for (action, payload) in calls {
+            if let Some(schemas) = &schemas {
+                schemas
+                    .validate(&action.item, Direction::Request, &payload)
+                    .map_err(|errors| schema_error(&action, "request", errors))?;
+            }
+            let result = charge_point.call(&action.item, payload, action.span)?;
+            if let Some(schemas) = &schemas {
+                schemas
+                    .validate(&action.item, Direction::Response, &result)
+                    .map_err(|errors| schema_error(&action, "response", errors))?;
+            }
+            results.push(json_to_value(result, head));
+        }
+
+        Ok(PipelineData::Value(
+            if single {
+                results.remove(0)
+            } else {
+                Value::list(results, head)
+            },
+            None,
+        ))
+    }
+}
+
+pub struct WebSocketOcppServe;
+
+impl PluginCommand for WebSocketOcppServe {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws ocpp serve"
+    }
+
+    fn description(&self) -> &str {
+        "act as an OCPP central system and stream the calls charge points make"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Charge points connect to ws://<bind>:<port>/<anything>/<identity> with the ocpp1.6 or \
+         ocpp2.0.1 subprotocol. Each call is passed to the --handler closure as \
+         {charge_point, protocol, action, payload}, and the record it returns is sent back as \
+         the result; returning nothing, or having no handler, sends a minimal accepting reply. \
+         Every call is output as {charge_point, action, payload, response, error}. Serving \
+         stops after --max-time or when interrupted."
+    }
+
+    fn signature(&self) -> Signature {
+        server_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))]),
+            DEFAULT_PORT,
+        )
+        .named(
+            "handler",
+            SyntaxShape::Closure(Some(vec![SyntaxShape::Record(vec![])])),
+            "closure that receives each call and returns the response payload",
+            None,
+        )
+        .named(
+            "schemas",
+            SyntaxShape::Directory,
+            "validate payloads against the OCPP JSON schemas in this directory",
+            None,
+        )
+        .named(
+            "max-time",
+            SyntaxShape::Duration,
+            "stop serving after this long",
+            Some('m'),
+        )
+        .named(
+            "verbose",
+            SyntaxShape::Int,
+            "verbosity level (0=error, 1=warn, 2=info, 3=debug, 4=trace)",
+            Some('v'),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        init_logging(call)?;
+        let head = call.head;
+
+        let listener = listen(call, DEFAULT_PORT)?;
+        if let Ok(address) = listener.local_addr() {
+            log::info!("Central system listening on {address}");
+        }
+
+        let stop = Stop::default();
+        let central = Arc::new(CentralSystem {
+            engine: engine.clone(),
+            handler: call.get_flag("handler")?,
+            schemas: schemas_flag(call, engine)?,
+            stop: stop.clone(),
+            next_transaction_id: AtomicU64::new(1),
+            span: head,
+        });
+        let (tx, rx) = mpsc::channel();
+        let stop = spawn_accept_loop(listener, stop, move |stream, peer| {
+            central.serve(stream, peer, tx.clone())
+        });
+        serve_values(call, engine, rx, stop)
+    }
+}
+
+/// An OCPP-J message: `[2, id, action, payload]`, `[3, id, payload]` or
+/// `[4, id, code, description, details]`.
+#[derive(Debug)]
+enum Frame {
+    Call {
+        id: String,
+        action: String,
+        payload: serde_json::Value,
+    },
+    Result {
+        id: String,
+        payload: serde_json::Value,
+    },
+    Error {
+        id: String,
+        code: String,
+        description: String,
+        details: serde_json::Value,
+    },
+}
+
+impl Frame {
+    fn parse(message: serde_json::Value) -> Result<Self, String> {
+        let serde_json::Value::Array(mut fields) = message else {
+            return Err("not an array".into());
+        };
+        let kind = fields.first().and_then(|kind| kind.as_u64());
+        let id = fields
+            .get(1)
+            .and_then(|id| id.as_str())
+            .ok_or("missing message id")?
+            .to_string();
+        let mut field = |i: usize| {
+            fields
+                .get_mut(i)
+                .map(serde_json::Value::take)
+                .unwrap_or_default()
+        };
+        match kind {
+            Some(CALL) => Ok(Frame::Call {
+                id,
+                action: field(2).as_str().ok_or("missing action")?.to_string(),
+                payload: field(3),
+            }),
+            Some(CALL_RESULT) => Ok(Frame::Result {
+                id,
+                payload: field(2),
+            }),
+            Some(CALL_ERROR) => Ok(Frame::Error {
+                id,
+                code: field(2).as_str().unwrap_or("GenericError").to_string(),
+                description: field(3).as_str().unwrap_or_default().to_string(),
+                details: field(4),
+            }),
+            _ => Err("unknown message type".into()),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Frame::Call {
+                id,
+                action,
+                payload,
+            } => json!([CALL, id, action, payload]),
+            Frame::Result { id, payload } => json!([CALL_RESULT, id, payload]),
+            Frame::Error {
+                id,
+                code,
+                description,
+                details,
+            } => json!([CALL_ERROR, id, code, description, details]),
+        }
+    }
+}
+
+/// The client side: sends calls with increasing ids and waits for the matching reply.
+struct ChargePoint {
+    session: Session,
+    next_id: u64,
+}
+
+impl ChargePoint {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            next_id: 1,
+        }
+    }
+
+    fn call(
+        &mut self,
+        action: &str,
+        payload: serde_json::Value,
+        span: Span,
+    ) -> Result<serde_json::Value, LabeledError> {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+
+        let request = Frame::Call {
+            id: id.clone(),
+            action: action.to_string(),
+            payload,
+        };
+        log::debug!("Calling {action} as {id}");
+        self.session.send_json(&request.to_json())?;
+
+        loop {
+            let Some(message) = self.session.recv_json()? else {
+                let reason = if self.session.timed_out() {
+                    "timed out"
+                } else {
+                    "connection closed"
+                };
+                return Err(LabeledError::new(format!("No OCPP response: {reason}"))
+                    .with_label(format!("{reason} before this call was answered"), span));
+            };
+            match Frame::parse(message) {
+                Ok(Frame::Result { id: reply, payload }) if reply == id => return Ok(payload),
+                Ok(Frame::Error {
+                    id: reply,
+                    code,
+                    description,
+                    details,
+                }) if reply == id => {
+                    let mut error = LabeledError::new(format!("OCPP error {code}: {description}"))
+                        .with_label(format!("the central system rejected {action}"), span);
+                    if details.as_object().is_some_and(|d| !d.is_empty()) {
+                        error = error.with_help(format!("details: {details}"));
+                    }
+                    return Err(error);
+                }
+                Ok(Frame::Call {
+                    id: request,
+                    action: requested,
+                    ..
+                }) => {
+                    log::warn!("Refusing {requested} call from the central system");
+                    let refusal = Frame::Error {
+                        id: request,
+                        code: "NotImplemented".into(),
+                        description: format!("{requested} is not supported by this client"),
+                        details: json!({}),
+                    };
+                    self.session.send_json(&refusal.to_json())?;
+                }
+                Ok(other) => log::debug!("Ignoring reply to another call: {other:?}"),
+                Err(reason) => log::debug!("Ignoring malformed message: {reason}"),
+            }
+        }
+    }
+}
+
+/// A CALLERROR to send back to a charge point.
+struct CallError {
+    code: &'static str,
+    description: String,
+}
+
+/// The server side, shared by the accept loop and one thread per charge point.
+struct CentralSystem {
+    engine: EngineInterface,
+    handler: Option<Spanned<Closure>>,
+    schemas: Option<Schemas>,
+    stop: Stop,
+    next_transaction_id: AtomicU64,
+    span: Span,
+}
+
+impl CentralSystem {
+    fn stopped(&self) -> bool {
+        self.stop.is_set()
+    }
+
+    fn serve(&self, stream: TcpStream, peer: SocketAddr, tx: Sender<Value>) {
+        let mut accepted = match accept(stream, &SUBPROTOCOLS) {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("Handshake with {peer} failed: {e}");
+                return;
+            }
+        };
+        let charge_point = accepted
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let protocol = accepted.subprotocol.clone().unwrap_or_default();
+        log::info!("Charge point {charge_point} connected from {peer} using {protocol}");
+
+        while !self.stopped() {
+            let text = match accepted.socket.read() {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(e) if is_read_timeout(&e) => continue,
+                Err(e) => {
+                    log::debug!("Connection to {charge_point} ended: {e}");
+                    break;
+                }
+            };
+            let frame = serde_json::from_str(&text)
+                .map_err(|e| e.to_string())
+                .and_then(Frame::parse);
+            let (id, action, payload) = match frame {
+                Ok(Frame::Call {
+                    id,
+                    action,
+                    payload,
+                }) => (id, action, payload),
+                Ok(other) => {
+                    log::debug!("Ignoring {other:?} from {charge_point}");
+                    continue;
+                }
+                Err(reason) => {
+                    log::warn!("Ignoring malformed message from {charge_point}: {reason}");
+                    continue;
+                }
+            };
+            log::debug!("{charge_point} called {action} as {id}");
+
+            let outcome = self.respond(&charge_point, &protocol, &action, &payload);
+            let reply = match &outcome {
+                Ok(response) => Frame::Result {
+                    id,
+                    payload: response.clone(),
+                },
+                Err(error) => Frame::Error {
+                    id,
+                    code: error.code.into(),
+                    description: error.description.clone(),
+                    details: json!({}),
+                },
+            };
+            if let Err(e) = accepted
+                .socket
+                .send(Message::Text(reply.to_json().to_string()))
+            {
+                log::warn!("Failed to reply to {charge_point}: {e}");
+                break;
+            }
+
+            let span = self.span;
+            let mut record = Record::new();
+            record.push("charge_point", Value::string(&charge_point, span));
+            record.push("action", Value::string(&action, span));
+            record.push("payload", json_to_value(payload, span));
+            match outcome {
+                Ok(response) => {
+                    record.push("response", json_to_value(response, span));
+                    record.push("error", Value::nothing(span));
+                }
+                Err(error) => {
+                    let mut details = Record::new();
+                    details.push("code", Value::string(error.code, span));
+                    details.push("description", Value::string(error.description, span));
+                    record.push("response", Value::nothing(span));
+                    record.push("error", Value::record(details, span));
+                }
+            }
+            if tx.send(Value::record(record, span)).is_err() {
+                break;
+            }
+        }
+
+        let _ = accepted.socket.close(None);
+        let _ = accepted.socket.flush();
+    }
+
+    fn respond(
+        &self,
+        charge_point: &str,
+        protocol: &str,
+        action: &str,
+        payload: &serde_json::Value,
+    ) -> Result<serde_json::Value, CallError> {
+        if let Some(schemas) = &self.schemas {
+            schemas
+                .validate(action, Direction::Request, payload)
+                .map_err(|errors| CallError {
+                    code: format_violation(protocol),
+                    description: errors.join("; "),
+                })?;
+        }
+
+        let internal = |description: String| CallError {
+            code: "InternalError",
+            description,
+        };
+        let handled = match &self.handler {
+            Some(handler) => {
+                let span = self.span;
+                let mut request = Record::new();
+                request.push("charge_point", Value::string(charge_point, span));
+                request.push("protocol", Value::string(protocol, span));
+                request.push("action", Value::string(action, span));
+                request.push("payload", json_to_value(payload.clone(), span));
+                match self
+                    .engine
+                    .eval_closure(handler, vec![Value::record(request, span)], None)
+                {
+                    Ok(Value::Nothing { .. }) => None,
+                    Ok(response) => {
+                        Some(value_to_json(&response).map_err(|e| internal(e.to_string()))?)
+                    }
+                    Err(e) => return Err(internal(e.to_string())),
+                }
+            }
+            None => None,
+        };
+        let response = handled.unwrap_or_else(|| self.default_response(protocol, action));
+
+        if let Some(schemas) = &self.schemas {
+            schemas
+                .validate(action, Direction::Response, &response)
+                .map_err(|errors| {
+                    internal(format!(
+                        "response does not match its schema: {}",
+                        errors.join("; ")
+                    ))
+                })?;
+        }
+        Ok(response)
+    }
+
+    /// The minimal reply accepting `action`, for calls the handler leaves alone.
+    fn default_response(&self, protocol: &str, action: &str) -> serde_json::Value {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let accepted = json!({ "status": "Accepted" });
+        match action {
+            "BootNotification" => json!({
+                "status": "Accepted",
+                "currentTime": now,
+                "interval": DEFAULT_HEARTBEAT_INTERVAL,
+            }),
+            "Heartbeat" => json!({ "currentTime": now }),
+            "Authorize" if protocol == "ocpp1.6" => json!({ "idTagInfo": accepted }),
+            "Authorize" => json!({ "idTokenInfo": accepted }),
+            "StartTransaction" => json!({
+                "transactionId": self.next_transaction_id.fetch_add(1, Ordering::Relaxed),
+                "idTagInfo": accepted,
+            }),
+            "DataTransfer" => json!({ "status": "UnknownVendorId" }),
+            _ => json!({}),
+        }
+    }
+}
+
+/// The error code for a malformed payload, which was renamed in OCPP 2.0.1.
+fn format_violation(protocol: &str) -> &'static str {
+    if protocol == "ocpp1.6" {
+        "FormationViolation"
+    } else {
+        "FormatViolation"
+    }
+}
+
+fn schemas_flag(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+) -> Result<Option<Schemas>, LabeledError> {
+    let Some(dir) = call.get_flag::<Spanned<String>>("schemas")? else {
+        return Ok(None);
+    };
+    let path = resolve_path(engine, &dir)?;
+    if !path.is_dir() {
+        return Err(LabeledError::new("Schema directory not found")
+            .with_label("expected a directory of OCPP JSON schemas", dir.span));
+    }
+    Ok(Some(Schemas::new(path)))
+}
+
+fn schema_error(action: &Spanned<String>, kind: &str, errors: Vec<String>) -> LabeledError {
+    LabeledError::new(format!("Invalid {} {kind}", action.item))
+        .with_label(errors.join("; "), action.span)
+        .with_help("the payload does not match the OCPP JSON schema for this action")
+}
+
+fn call_entry(value: &Value) -> Result<(Spanned<String>, serde_json::Value), LabeledError> {
+    let invalid = || {
+        LabeledError::new("Invalid OCPP call").with_label(
+            "expected a record with an `action` and optional `payload`",
+            value.span(),
+        )
+    };
+    let record = value.as_record().map_err(|_| invalid())?;
+    let action = record.get("action").ok_or_else(invalid)?;
+    let payload = record
+        .get("payload")
+        .map(value_to_json)
+        .transpose()?
+        .unwrap_or_else(|| json!({}));
+    Ok((
+        Spanned {
+            item: action.coerce_string()?,
+            span: action.span(),
+        },
+        payload,
+    ))
+}
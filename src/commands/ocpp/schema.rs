@@ -0,0 +1,252 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+/// Validates OCPP payloads against the JSON schemas published with the specification.
+///
+/// Only the keywords those schemas use are implemented: `type`, `properties`, `required`,
+/// `additionalProperties`, `enum`, `const`, length, range and item limits, `multipleOf`,
+/// local `$ref`s and the `allOf`/`anyOf`/`oneOf` combinators. `format` is not checked.
+pub struct Schemas {
+    dir: PathBuf,
+}
+
+/// Which side of a call a payload belongs to.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl Schemas {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Validates `payload`, returning one message per violation.
+    ///
+    /// Actions without a schema file in the directory are not checked.
+    pub fn validate(
+        &self,
+        action: &str,
+        direction: Direction,
+        payload: &Value,
+    ) -> Result<(), Vec<String>> {
+        // OCPP 1.6 names request schemas after the action, 2.0.1 adds a Request suffix
+        let candidates = match direction {
+            Direction::Request => vec![format!("{action}Request.json"), format!("{action}.json")],
+            Direction::Response => vec![format!("{action}Response.json")],
+        };
+        let Some(path) = candidates
+            .iter()
+            .map(|name| self.dir.join(name))
+            .find(|path| path.is_file())
+        else {
+            log::debug!("No schema for {action} in {}", self.dir.display());
+            return Ok(());
+        };
+
+        let schema = load(&path).map_err(|e| vec![e])?;
+        let mut errors = vec![];
+        check(&schema, &schema, payload, "", &[], &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("invalid schema {}: {e}", path.display()))
+}
+
+/// Checks `value` against `schema`. `refs` are the references followed since the last step
+/// into a property or item: meeting one again means the schema loops without ever reaching
+/// a smaller value.
+fn check(
+    root: &Value,
+    schema: &Value,
+    value: &Value,
+    at: &str,
+    refs: &[&str],
+    errors: &mut Vec<String>,
+) {
+    let location = if at.is_empty() { "payload" } else { at };
+
+    if let Some(reference) = schema["$ref"].as_str() {
+        if refs.contains(&reference) {
+            errors.push(format!("{location}: circular $ref {reference}"));
+            return;
+        }
+        match resolve(root, reference) {
+            Some(target) => check(
+                root,
+                target,
+                value,
+                at,
+                &[refs, &[reference]].concat(),
+                errors,
+            ),
+            None => errors.push(format!("{location}: unresolvable $ref {reference}")),
+        }
+        return;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{location}: expected {}, found {}",
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{location}: {value} is not one of {}",
+                schema["enum"]
+            ));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            errors.push(format!("{location}: expected {constant}"));
+        }
+    }
+
+    match value {
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(max) = schema["maxLength"].as_u64().filter(|max| len > *max) {
+                errors.push(format!("{location}: longer than {max} characters"));
+            }
+            if let Some(min) = schema["minLength"].as_u64().filter(|min| len < *min) {
+                errors.push(format!("{location}: shorter than {min} characters"));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(max) = schema["maximum"].as_f64().filter(|max| n > *max) {
+                errors.push(format!("{location}: greater than {max}"));
+            }
+            if let Some(min) = schema["minimum"].as_f64().filter(|min| n < *min) {
+                errors.push(format!("{location}: less than {min}"));
+            }
+            if let Some(step) = schema["multipleOf"].as_f64().filter(|step| *step > 0.0) {
+                let ratio = n / step;
+                if (ratio - ratio.round()).abs() > 1e-9 {
+                    errors.push(format!("{location}: not a multiple of {step}"));
+                }
+            }
+        }
+        Value::Array(items) => {
+            let len = items.len() as u64;
+            if let Some(max) = schema["maxItems"].as_u64().filter(|max| len > *max) {
+                errors.push(format!("{location}: more than {max} items"));
+            }
+            if let Some(min) = schema["minItems"].as_u64().filter(|min| len < *min) {
+                errors.push(format!("{location}: fewer than {min} items"));
+            }
+            if let Some(item_schema) = schema.get("items").filter(|s| s.is_object()) {
+                for (i, item) in items.iter().enumerate() {
+                    check(root, item_schema, item, &format!("{at}/{i}"), &[], errors);
+                }
+            }
+        }
+        Value::Object(fields) => {
+            if let Some(required) = schema["required"].as_array() {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !fields.contains_key(name) {
+                        errors.push(format!("{location}: missing required property {name}"));
+                    }
+                }
+            }
+            let properties = schema["properties"].as_object();
+            for (name, field) in fields {
+                match properties.and_then(|p| p.get(name)) {
+                    Some(field_schema) => check(
+                        root,
+                        field_schema,
+                        field,
+                        &format!("{at}/{name}"),
+                        &[],
+                        errors,
+                    ),
+                    None if schema["additionalProperties"] == Value::Bool(false) => {
+                        errors.push(format!("{location}: unexpected property {name}"))
+                    }
+                    None => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(all) = schema["allOf"].as_array() {
+        for sub in all {
+            check(root, sub, value, at, refs, errors);
+        }
+    }
+    for (keyword, wanted) in [("anyOf", None), ("oneOf", Some(1))] {
+        if let Some(options) = schema[keyword].as_array() {
+            let passing = options
+                .iter()
+                .filter(|sub| {
+                    let mut sub_errors = vec![];
+                    check(root, sub, value, at, refs, &mut sub_errors);
+                    sub_errors.is_empty()
+                })
+                .count();
+            let ok = match wanted {
+                Some(exactly) => passing == exactly,
+                None => passing > 0,
+            };
+            if !ok {
+                errors.push(format!("{location}: does not match {keyword}"));
+            }
+        }
+    }
+}
+
+/// Resolves a local reference such as `#/definitions/IdTagInfo`.
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use chrono::{DateTime, Utc};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+
+use tungstenite::protocol::frame::coding::{Data, OpCode};
+
+use super::{
+    frame::{opcode_value, payload_value},
+    resolve_path,
+};
+use crate::{
+    ws::{
+        frame::{self, Frame, FrameError, Sequence},
+        pcap::{self, Reassembler, Segment},
+        recording::Direction,
+    },
+    WebSocketPlugin,
+};
+
+/// How much of a stream to look through for the end of the HTTP head.
+const MAX_HEADERS: usize = 64 * 1024;
+
+pub struct WebSocketFromPcap;
+
+impl PluginCommand for WebSocketFromPcap {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws from-pcap"
+    }
+
+    fn description(&self) -> &str {
+        "extract WebSocket messages from a packet capture"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Reads a pcap or pcapng file, reassembles its TCP streams and finds the HTTP upgrades \
+         to WebSocket in them. The messages of each upgraded connection are unmasked, put back \
+         together from their fragments and output as {conn, direction, ts, opcode, payload}, \
+         where conn numbers the connections in the order they were upgraded and direction is \
+         sent or received from the client's point of view. Traffic must be unencrypted, or \
+         already decrypted, and the capture must include the upgrade."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(PluginCommand::name(self))
+            .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+            .required(
+                "file",
+                SyntaxShape::Filepath,
+                "The pcap or pcapng file to read.",
+            )
+            .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let head = call.head;
+        let file: Spanned<String> = call.req(0)?;
+        let invalid = |e: String| {
+            LabeledError::new(format!("Could not read the capture: {e}"))
+                .with_label("while reading this file", file.span)
+        };
+        let data =
+            std::fs::read(resolve_path(engine, &file)?).map_err(|e| invalid(e.to_string()))?;
+        let segments = pcap::segments(&data).map_err(invalid)?;
+        log::debug!("Read {} TCP segments", segments.len());
+
+        let mut conversations = Conversations::default();
+        for segment in &segments {
+            conversations.push(segment);
+        }
+
+        Ok(PipelineData::Value(
+            Value::list(
+                conversations
+                    .messages
+                    .into_iter()
+                    .map(|message| message.into_value(head))
+                    .collect(),
+                head,
+            ),
+            None,
+        ))
+    }
+}
+
+/// A complete message found in a capture.
+struct CapturedMessage {
+    conn: u64,
+    direction: Direction,
+    time: DateTime<Utc>,
+    opcode: OpCode,
+    payload: Vec<u8>,
+}
+
+impl CapturedMessage {
+    fn into_value(self, span: Span) -> Value {
+        let mut record = Record::new();
+        record.push("conn", Value::int(self.conn as i64, span));
+        record.push("direction", Value::string(self.direction.as_str(), span));
+        record.push("ts", Value::date(self.time.fixed_offset(), span));
+        record.push("opcode", opcode_value(self.opcode, span));
+        record.push("payload", payload_value(self.opcode, self.payload, span));
+        Value::record(record, span)
+    }
+}
+
+#[derive(Default)]
+struct Conversations {
+    /// Keyed by the two endpoints, lowest first.
+    connections: HashMap<(SocketAddr, SocketAddr), Connection>,
+    upgraded: u64,
+    messages: Vec<CapturedMessage>,
+}
+
+impl Conversations {
+    fn push(&mut self, segment: &Segment) {
+        let (key, index) = if segment.source <= segment.destination {
+            ((segment.source, segment.destination), 0)
+        } else {
+            ((segment.destination, segment.source), 1)
+        };
+        let connection = self.connections.entry(key).or_default();
+        if segment.syn && connection.streams.iter().any(|stream| stream.started()) {
+            log::debug!("New connection between {} and {}", key.0, key.1);
+            *connection = Connection::default();
+        }
+
+        let stream = &mut connection.streams[index];
+        let data = stream.reassembler.push(segment);
+        stream.buffer.extend(data);
+        connection.advance(segment.time, &mut self.upgraded, &mut self.messages);
+
+        if segment.rst {
+            self.connections.remove(&key);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Connection {
+    /// The two directions of the connection, in the order of the key.
+    streams: [Stream; 2],
+    state: State,
+}
+
+#[derive(Default)]
+enum State {
+    /// Waiting for the upgrade request and response.
+    #[default]
+    Handshake,
+    Open {
+        conn: u64,
+        /// Which of the streams is the client's.
+        client: usize,
+    },
+    /// Not a WebSocket connection, or one whose start was not captured.
+    Ignored,
+}
+
+impl Connection {
+    fn advance(
+        &mut self,
+        time: DateTime<Utc>,
+        upgraded: &mut u64,
+        messages: &mut Vec<CapturedMessage>,
+    ) {
+        if let State::Handshake = self.state {
+            for stream in &mut self.streams {
+                stream.read_http();
+            }
+            self.state = match [self.streams[0].http, self.streams[1].http] {
+                [Some(Http::Upgrade), Some(Http::SwitchingProtocols)] => State::Open {
+                    conn: *upgraded + 1,
+                    client: 0,
+                },
+                [Some(Http::SwitchingProtocols), Some(Http::Upgrade)] => State::Open {
+                    conn: *upgraded + 1,
+                    client: 1,
+                },
+                [Some(Http::Upgrade), None] | [None, Some(Http::Upgrade)] | [None, None]
+                    if self
+                        .streams
+                        .iter()
+                        .all(|stream| stream.buffer.len() <= MAX_HEADERS) =>
+                {
+                    return
+                }
+                _ => State::Ignored,
+            };
+            if let State::Open { conn, client } = self.state {
+                log::debug!("Connection {conn} upgraded to WebSocket");
+                *upgraded = conn;
+                for (index, stream) in self.streams.iter_mut().enumerate() {
+                    stream.sequence = Sequence::new(Some(index == client));
+                }
+            }
+        }
+
+        let State::Open { conn, client } = self.state else {
+            for stream in &mut self.streams {
+                stream.buffer.clear();
+            }
+            return;
+        };
+        for (index, stream) in self.streams.iter_mut().enumerate() {
+            let direction = if index == client {
+                Direction::Sent
+            } else {
+                Direction::Received
+            };
+            stream.read_frames(conn, direction, time, messages);
+        }
+    }
+}
+
+/// What the HTTP head at the start of a stream was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Http {
+    /// A request to upgrade to WebSocket.
+    Upgrade,
+    /// A `101 Switching Protocols` response.
+    SwitchingProtocols,
+    Other,
+}
+
+#[derive(Default)]
+struct Stream {
+    reassembler: Reassembler,
+    /// Reassembled data not handled yet.
+    buffer: Vec<u8>,
+    /// The HTTP head, once it was read.
+    http: Option<Http>,
+    /// Checks the frames like a live connection would.
+    sequence: Sequence,
+    /// The opcode and payload of a fragmented message being put together.
+    fragments: Option<(OpCode, Vec<u8>)>,
+    /// Set once a frame could not be decoded or broke the protocol, after which the stream
+    /// is not followed.
+    broken: bool,
+}
+
+impl Stream {
+    fn started(&self) -> bool {
+        !self.buffer.is_empty() || self.http.is_some()
+    }
+
+    /// Reads the HTTP head at the start of the stream once it is complete.
+    fn read_http(&mut self) {
+        if self.http.is_some() {
+            return;
+        }
+        let Some(end) = self
+            .buffer
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        else {
+            return;
+        };
+        let head = String::from_utf8_lossy(&self.buffer[..end]).to_ascii_lowercase();
+        let mut lines = head.lines();
+        let first = lines.next().unwrap_or_default();
+        let mut headers = lines.filter_map(|line| line.split_once(':'));
+
+        self.http = Some(if first.starts_with("http/") {
+            match first.split_whitespace().nth(1) {
+                Some("101") => Http::SwitchingProtocols,
+                _ => Http::Other,
+            }
+        } else if first.starts_with("get ")
+            && headers.any(|(name, value)| name.trim() == "upgrade" && value.trim() == "websocket")
+        {
+            Http::Upgrade
+        } else {
+            Http::Other
+        });
+        self.buffer.drain(..end + 4);
+    }
+
+    /// Decodes the complete frames in the buffer, adding each complete message.
+    fn read_frames(
+        &mut self,
+        conn: u64,
+        direction: Direction,
+        time: DateTime<Utc>,
+        messages: &mut Vec<CapturedMessage>,
+    ) {
+        let mut consumed = 0;
+        while !self.broken {
+            let decoded = frame::decode(&self.buffer[consumed..]).and_then(|(frame, len)| {
+                frame::validate(&frame)?;
+                self.sequence.check(&frame)?;
+                Ok((frame, len))
+            });
+            let (frame, len) = match decoded {
+                Ok(decoded) => decoded,
+                Err(FrameError::Incomplete { .. }) => break,
+                Err(e) => {
+                    log::warn!("Connection {conn}: {e}, no longer following {direction:?} frames");
+                    self.broken = true;
+                    break;
+                }
+            };
+            consumed += len;
+            if let Some((opcode, payload)) = self.message(frame) {
+                messages.push(CapturedMessage {
+                    conn,
+                    direction,
+                    time,
+                    opcode,
+                    payload,
+                });
+            }
+        }
+        self.buffer.drain(..consumed);
+        if self.broken {
+            self.buffer.clear();
+        }
+    }
+
+    /// Puts fragmented messages back together, returning each message once it is complete.
+    ///
+    /// The frames were checked by the sequence, so continuations always have a message to
+    /// continue.
+    fn message(&mut self, frame: Frame) -> Option<(OpCode, Vec<u8>)> {
+        let header = frame.header().clone();
+        let (opcode, payload) = match (header.opcode, self.fragments.take()) {
+            (OpCode::Data(Data::Continue), Some((opcode, mut payload))) => {
+                payload.extend(frame.into_data());
+                (opcode, payload)
+            }
+            (OpCode::Control(_), fragments) => {
+                self.fragments = fragments;
+                return Some((header.opcode, frame.into_data()));
+            }
+            (opcode, _) => (opcode, frame.into_data()),
+        };
+        if header.is_final {
+            return Some((opcode, payload));
+        }
+        self.fragments = Some((opcode, payload));
+        None
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use serde_json::json;
+
+use super::{connection_flags, input_values, open_session_to, session_url, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::{parse_json, Received, Session},
+    },
+    WebSocketPlugin,
+};
+
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(30);
+
+pub struct WebSocketPhoenix;
+
+impl PluginCommand for WebSocketPhoenix {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws phoenix"
+    }
+
+    fn description(&self) -> &str {
+        "join a Phoenix Channels topic and stream its broadcasts and replies"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Pipeline input is pushed to the channel after joining, as {event, payload} records. \
+         The URL is the socket endpoint, e.g. wss://example.com/socket/websocket; the `vsn` \
+         query parameter is added to match the serializer unless it is already present."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::list(Type::record())),
+                    (Type::record(), Type::list(Type::record())),
+                    (Type::list(Type::record()), Type::list(Type::record())),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL of the Phoenix socket (ws:// or wss://).",
+                )
+                .required("topic", SyntaxShape::String, "The topic to join.")
+                .named(
+                    "payload",
+                    SyntaxShape::Record(vec![]),
+                    "payload sent with the join",
+                    Some('p'),
+                )
+                .named(
+                    "serializer",
+                    SyntaxShape::String,
+                    "wire format: v1 (objects) or v2 (arrays, the default)",
+                    None,
+                )
+                .named(
+                    "heartbeat",
+                    SyntaxShape::Duration,
+                    "interval between heartbeats (default 30sec)",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let topic: Spanned<String> = call.req(1)?;
+        let payload = call
+            .get_flag::<Value>("payload")?
+            .as_ref()
+            .map(value_to_json)
+            .transpose()?
+            .unwrap_or_else(|| json!({}));
+        let serializer = match call.get_flag::<Spanned<String>>("serializer")? {
+            None => Serializer::V2,
+            Some(Spanned { item, .. }) if item == "v2" || item == "2.0.0" => Serializer::V2,
+            Some(Spanned { item, .. }) if item == "v1" || item == "1.0.0" => Serializer::V1,
+            Some(Spanned { span, .. }) => {
+                return Err(
+                    LabeledError::new("Unknown serializer").with_label("expected v1 or v2", span)
+                )
+            }
+        };
+        let heartbeat = match call.get_flag::<Value>("heartbeat")? {
+            Some(val) => match val.as_duration()? {
+                nanos if nanos > 0 => Duration::from_nanos(nanos as u64),
+                _ => {
+                    return Err(LabeledError::new("Invalid heartbeat")
+                        .with_label("the interval must be positive", val.span()))
+                }
+            },
+            None => DEFAULT_HEARTBEAT,
+        };
+        let pushes = input_values(input)?
+            .iter()
+            .map(push_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (mut url, span) = session_url(call)?;
+        if !url.query_pairs().any(|(k, _)| k == "vsn") {
+            url.query_pairs_mut().append_pair("vsn", serializer.vsn());
+        }
+
+        let session = open_session_to(call, engine, &url, span, &[])?;
+        let mut channel = PhoenixChannel::new(session, serializer, topic.item, heartbeat);
+
+        channel.join(payload, topic.span)?;
+        for (event, payload) in pushes {
+            channel.push(&event, payload)?;
+        }
+
+        let head = call.head;
+        Ok(stream_values(
+            move || Ok(channel.next()?.map(|message| message.into_value(head))),
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// The Phoenix wire formats, selected on the server by the `vsn` query parameter.
+#[derive(Clone, Copy)]
+enum Serializer {
+    /// `{"join_ref", "ref", "topic", "event", "payload"}` objects.
+    V1,
+    /// `[join_ref, ref, topic, event, payload]` arrays.
+    V2,
+}
+
+impl Serializer {
+    fn vsn(self) -> &'static str {
+        match self {
+            Serializer::V1 => "1.0.0",
+            Serializer::V2 => "2.0.0",
+        }
+    }
+
+    fn encode(self, message: &PhoenixMessage) -> serde_json::Value {
+        match self {
+            Serializer::V1 => json!({
+                "join_ref": message.join_ref,
+                "ref": message.msg_ref,
+                "topic": message.topic,
+                "event": message.event,
+                "payload": message.payload,
+            }),
+            Serializer::V2 => json!([
+                message.join_ref,
+                message.msg_ref,
+                message.topic,
+                message.event,
+                message.payload,
+            ]),
+        }
+    }
+
+    fn decode(self, mut json: serde_json::Value) -> Option<PhoenixMessage> {
+        let text = |v: &serde_json::Value| v.as_str().map(str::to_string);
+        match (self, &mut json) {
+            (Serializer::V1, serde_json::Value::Object(map)) => Some(PhoenixMessage {
+                join_ref: map.get("join_ref").and_then(text),
+                msg_ref: map.get("ref").and_then(text),
+                topic: map.get("topic").and_then(text)?,
+                event: map.get("event").and_then(text)?,
+                payload: map.remove("payload").unwrap_or_default(),
+            }),
+            (Serializer::V2, serde_json::Value::Array(items)) if items.len() == 5 => {
+                Some(PhoenixMessage {
+                    join_ref: text(&items[0]),
+                    msg_ref: text(&items[1]),
+                    topic: text(&items[2])?,
+                    event: text(&items[3])?,
+                    payload: items[4].take(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+struct PhoenixMessage {
+    join_ref: Option<String>,
+    msg_ref: Option<String>,
+    topic: String,
+    event: String,
+    payload: serde_json::Value,
+}
+
+impl PhoenixMessage {
+    fn into_value(self, span: Span) -> Value {
+        let mut record = Record::new();
+        record.push("topic", Value::string(self.topic, span));
+        record.push("event", Value::string(self.event, span));
+        record.push("payload", json_to_value(self.payload, span));
+        record.push(
+            "ref",
+            self.msg_ref
+                .map(|r| Value::string(r, span))
+                .unwrap_or_else(|| Value::nothing(span)),
+        );
+        Value::record(record, span)
+    }
+}
+
+/// A joined channel on a Phoenix socket.
+struct PhoenixChannel {
+    session: Session,
+    serializer: Serializer,
+    topic: String,
+    join_ref: Option<String>,
+    next_ref: u64,
+    heartbeat: Duration,
+    next_heartbeat: Instant,
+    /// Messages for the topic that arrived while waiting for the join reply.
+    pending: VecDeque<PhoenixMessage>,
+}
+
+impl PhoenixChannel {
+    fn new(session: Session, serializer: Serializer, topic: String, heartbeat: Duration) -> Self {
+        Self {
+            session,
+            serializer,
+            topic,
+            join_ref: None,
+            next_ref: 1,
+            heartbeat,
+            next_heartbeat: Instant::now() + heartbeat,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn send(
+        &mut self,
+        topic: &str,
+        event: &str,
+        payload: serde_json::Value,
+    ) -> Result<String, LabeledError> {
+        let msg_ref = self.next_ref.to_string();
+        self.next_ref += 1;
+
+        let message = PhoenixMessage {
+            join_ref: self.join_ref.clone().filter(|_| topic == self.topic),
+            msg_ref: Some(msg_ref.clone()),
+            topic: topic.to_string(),
+            event: event.to_string(),
+            payload,
+        };
+        self.session.send_json(&self.serializer.encode(&message))?;
+        Ok(msg_ref)
+    }
+
+    fn join(&mut self, payload: serde_json::Value, topic_span: Span) -> Result<(), LabeledError> {
+        log::debug!("Joining Phoenix topic {}", self.topic);
+
+        let join_ref = self.next_ref.to_string();
+        self.join_ref = Some(join_ref.clone());
+        let topic = self.topic.clone();
+        self.send(&topic, "phx_join", payload)?;
+
+        loop {
+            let Some(message) = self.receive()? else {
+                return Err(LabeledError::new("Phoenix join failed")
+                    .with_label("connection ended before the join was answered", topic_span));
+            };
+            if message.event == "phx_reply" && message.msg_ref.as_deref() == Some(&join_ref) {
+                let status = message.payload["status"].as_str().unwrap_or_default();
+                if status == "ok" {
+                    log::debug!("Joined Phoenix topic {}", self.topic);
+                    return Ok(());
+                }
+                return Err(LabeledError::new(format!("Phoenix join failed: {status}"))
+                    .with_label("the server refused to join this topic", topic_span)
+                    .with_help(format!("response: {}", message.payload["response"])));
+            }
+            self.pending.push_back(message);
+        }
+    }
+
+    fn push(&mut self, event: &str, payload: serde_json::Value) -> Result<(), LabeledError> {
+        log::debug!("Pushing Phoenix event {event}");
+        let topic = self.topic.clone();
+        self.send(&topic, event, payload).map(|_| ())
+    }
+
+    /// Receives the next message for the joined topic, sending heartbeats while idle.
+    fn receive(&mut self) -> Result<Option<PhoenixMessage>, LabeledError> {
+        loop {
+            if Instant::now() >= self.next_heartbeat {
+                log::trace!("Sending Phoenix heartbeat");
+                self.send("phoenix", "heartbeat", json!({}))?;
+                self.next_heartbeat = Instant::now() + self.heartbeat;
+            }
+
+            let text = match self.session.recv_until(Some(self.next_heartbeat))? {
+                Received::Message(tungstenite::Message::Text(text)) => text,
+                Received::Message(other) => {
+                    log::debug!("Ignoring non-text message: {other:?}");
+                    continue;
+                }
+                Received::Idle => continue,
+                Received::Closed => return Ok(None),
+            };
+            let Some(message) = self
+                .serializer
+                .decode(parse_json(&text, self.session.span())?)
+            else {
+                log::debug!("Ignoring message in an unexpected format: {text}");
+                continue;
+            };
+            if message.topic != self.topic {
+                log::trace!("Ignoring message for topic {}", message.topic);
+                continue;
+            }
+            return Ok(Some(message));
+        }
+    }
+
+    /// The next broadcast or reply, ending once the channel or connection closes.
+    fn next(&mut self) -> Result<Option<PhoenixMessage>, LabeledError> {
+        let message = match self.pending.pop_front() {
+            Some(message) => message,
+            None => match self.receive()? {
+                Some(message) => message,
+                None => return Ok(None),
+            },
+        };
+        match message.event.as_str() {
+            "phx_close" => {
+                log::debug!("Phoenix channel closed");
+                Ok(None)
+            }
+            "phx_error" => Err(LabeledError::new("Phoenix channel crashed").with_label(
+                "the channel process on the server exited",
+                self.session.span(),
+            )),
+            _ => Ok(Some(message)),
+        }
+    }
+}
+
+fn push_entry(value: &Value) -> Result<(String, serde_json::Value), LabeledError> {
+    let invalid = || {
+        LabeledError::new("Invalid push").with_label(
+            "expected a record with an `event` and optional `payload`",
+            value.span(),
+        )
+    };
+    let record = value.as_record().map_err(|_| invalid())?;
+    let event = record.get("event").ok_or_else(invalid)?.coerce_string()?;
+    let payload = record
+        .get("payload")
+        .map(value_to_json)
+        .transpose()?
+        .unwrap_or_else(|| json!({}));
+    Ok((event, payload))
+}
@@ -0,0 +1,393 @@
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    engine::Closure, Category, LabeledError, PipelineData, Record, Signature, Span, Spanned,
+    SyntaxShape, Type, Value,
+};
+use serde_json::json;
+use sha2::Sha256;
+use url::Url;
+
+use super::{connection_flags, open_session_to, stream_values};
+use crate::{
+    ws::{
+        client::append_query_flag,
+        json::{json_to_value, value_to_json},
+        session::{parse_json, Received, Session},
+    },
+    WebSocketPlugin,
+};
+
+/// The Pusher Channels protocol revision this client speaks.
+const PROTOCOL_VERSION: &str = "7";
+
+/// How long to stay silent before pinging when the server does not say.
+const DEFAULT_ACTIVITY_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub struct WebSocketPusher;
+
+impl PluginCommand for WebSocketPusher {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws pusher"
+    }
+
+    fn description(&self) -> &str {
+        "subscribe to a Pusher Channels channel and stream its events"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Private and presence channels are authorized with --secret, or with an --auth closure \
+         that receives {socket_id, channel_name} and returns the {auth, channel_data} record \
+         an auth endpoint would. Presence channels signed with --secret also need \
+         --user-data. Events are output as {channel, event, data} records, with JSON data \
+         parsed."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+                .required("app-key", SyntaxShape::String, "The key of the Pusher app.")
+                .required_named(
+                    "channel",
+                    SyntaxShape::String,
+                    "the channel to subscribe to",
+                    Some('c'),
+                )
+                .named(
+                    "event",
+                    SyntaxShape::String,
+                    "only output events with this name",
+                    Some('e'),
+                )
+                .named(
+                    "cluster",
+                    SyntaxShape::String,
+                    "the cluster of the app, e.g. eu or us2 (default mt1)",
+                    None,
+                )
+                .named(
+                    "host",
+                    SyntaxShape::String,
+                    "connect to this host (or ws:// or wss:// URL) instead of the cluster",
+                    None,
+                )
+                .named(
+                    "secret",
+                    SyntaxShape::String,
+                    "app secret used to sign private and presence channel auth",
+                    None,
+                )
+                .named(
+                    "user-data",
+                    SyntaxShape::Record(vec![]),
+                    "presence channel member data, e.g. {user_id: 1, user_info: {name: me}}",
+                    None,
+                )
+                .named(
+                    "auth",
+                    SyntaxShape::Closure(Some(vec![SyntaxShape::Record(vec![])])),
+                    "closure that authorizes private and presence channels",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let app_key: Spanned<String> = call.req(0)?;
+        let channel: Spanned<String> = call
+            .get_flag("channel")?
+            .ok_or_else(|| LabeledError::new("Missing required flag --channel"))?;
+        let event: Option<String> = call.get_flag("event")?;
+        let secret: Option<String> = call.get_flag("secret")?;
+        let user_data: Option<Value> = call.get_flag("user-data")?;
+        let auth: Option<Spanned<Closure>> = call.get_flag("auth")?;
+
+        // Check the authorization inputs up front instead of failing after the handshake
+        let presence = channel.item.starts_with("presence-");
+        if (presence || channel.item.starts_with("private-")) && auth.is_none() {
+            if secret.is_none() {
+                return Err(LabeledError::new("Channel needs authorization").with_label(
+                    "private and presence channels need --secret or --auth",
+                    channel.span,
+                ));
+            }
+            if presence && user_data.is_none() {
+                return Err(LabeledError::new("Missing presence member data")
+                    .with_label(
+                        "presence channels signed with --secret need --user-data",
+                        channel.span,
+                    )
+                    .with_help("pass --user-data {user_id: ..., user_info: {...}}"));
+            }
+        }
+
+        let url = pusher_url(call, &app_key)?;
+        let mut pusher = Pusher::new(open_session_to(call, engine, &url, app_key.span, &[])?);
+
+        let socket_id = pusher.connect(app_key.span)?;
+
+        let mut data = json!({ "channel": channel.item });
+        if channel.item.starts_with("private-") || channel.item.starts_with("presence-") {
+            let authorization = match (&auth, &secret) {
+                (Some(closure), _) => {
+                    let mut request = Record::new();
+                    request.push("socket_id", Value::string(&socket_id, channel.span));
+                    request.push("channel_name", Value::string(&channel.item, channel.span));
+                    let response = engine.eval_closure(
+                        closure,
+                        vec![Value::record(request, channel.span)],
+                        None,
+                    )?;
+                    value_to_json(&response)?
+                }
+                (None, Some(secret)) => {
+                    let channel_data = user_data
+                        .as_ref()
+                        .map(value_to_json)
+                        .transpose()?
+                        .map(|user| user.to_string());
+                    sign_auth(
+                        &app_key.item,
+                        secret,
+                        &socket_id,
+                        &channel.item,
+                        channel_data,
+                    )
+                }
+                (None, None) => unreachable!("checked before connecting"),
+            };
+            if !authorization["auth"].is_string() {
+                return Err(LabeledError::new("Invalid channel authorization")
+                    .with_label("expected a record with an `auth` string", channel.span));
+            }
+            data["auth"] = authorization["auth"].clone();
+            if let Some(channel_data) = authorization.get("channel_data") {
+                data["channel_data"] = channel_data.clone();
+            }
+        }
+
+        pusher.subscribe(data, &channel)?;
+
+        let head = call.head;
+        let channel = channel.item;
+        Ok(stream_values(
+            move || {
+                while let Some(message) = pusher.next()? {
+                    let name = message["event"].as_str().unwrap_or_default();
+                    if message["channel"].as_str() != Some(&channel)
+                        || name.starts_with("pusher_internal:")
+                        || event.as_ref().is_some_and(|event| event != name)
+                    {
+                        log::trace!("Skipping event {name}");
+                        continue;
+                    }
+                    return Ok(Some(event_record(message, head)));
+                }
+                Ok(None)
+            },
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// Builds `wss://ws-<cluster>.pusher.com/app/<key>?protocol=7&...`, or the same path on --host.
+fn pusher_url(call: &EvaluatedCall, app_key: &Spanned<String>) -> Result<Url, LabeledError> {
+    let host: Option<Spanned<String>> = call.get_flag("host")?;
+    let cluster = call
+        .get_flag::<String>("cluster")?
+        .unwrap_or_else(|| "mt1".into());
+
+    let (base, span) = match host {
+        Some(host) if host.item.contains("://") => (host.item, host.span),
+        Some(host) => (format!("wss://{}", host.item), host.span),
+        None => (format!("wss://ws-{cluster}.pusher.com"), call.head),
+    };
+    let mut url = Url::parse(&base).map_err(|e| {
+        LabeledError::new(format!("Invalid Pusher host: {e}")).with_label("invalid host", span)
+    })?;
+    url.path_segments_mut()
+        .map_err(|_| {
+            LabeledError::new("Invalid Pusher host").with_label("cannot be a base URL", span)
+        })?
+        .pop_if_empty()
+        .extend(["app", &app_key.item]);
+    url.query_pairs_mut()
+        .append_pair("protocol", PROTOCOL_VERSION)
+        .append_pair("client", env!("CARGO_PKG_NAME"))
+        .append_pair("version", env!("CARGO_PKG_VERSION"))
+        .append_pair("flash", "false");
+    append_query_flag(call, &mut url)?;
+    Ok(url)
+}
+
+/// Signs a channel subscription the way a Pusher server library's `authorizeChannel` does.
+fn sign_auth(
+    app_key: &str,
+    secret: &str,
+    socket_id: &str,
+    channel: &str,
+    channel_data: Option<String>,
+) -> serde_json::Value {
+    let mut to_sign = format!("{socket_id}:{channel}");
+    if let Some(channel_data) = &channel_data {
+        to_sign.push(':');
+        to_sign.push_str(channel_data);
+    }
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(to_sign.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    let mut auth = json!({ "auth": format!("{app_key}:{signature}") });
+    if let Some(channel_data) = channel_data {
+        auth["channel_data"] = json!(channel_data);
+    }
+    auth
+}
+
+fn event_record(mut message: serde_json::Value, span: Span) -> Value {
+    // Event data is usually a JSON document encoded as a string
+    let data = match message["data"].take() {
+        serde_json::Value::String(text) => {
+            serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+        }
+        data => data,
+    };
+    let mut record = Record::new();
+    record.push("channel", json_to_value(message["channel"].take(), span));
+    record.push("event", json_to_value(message["event"].take(), span));
+    record.push("data", json_to_value(data, span));
+    Value::record(record, span)
+}
+
+struct Pusher {
+    session: Session,
+    activity_timeout: Duration,
+    last_activity: Instant,
+}
+
+impl Pusher {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            activity_timeout: DEFAULT_ACTIVITY_TIMEOUT,
+            last_activity: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, event: &str, data: serde_json::Value) -> Result<(), LabeledError> {
+        self.session
+            .send_json(&json!({ "event": event, "data": data }))
+    }
+
+    /// Waits for `pusher:connection_established` and returns the socket id.
+    fn connect(&mut self, span: Span) -> Result<String, LabeledError> {
+        let Some(message) = self.next()? else {
+            return Err(LabeledError::new("Pusher connection failed")
+                .with_label("connection ended before it was established", span));
+        };
+        if message["event"] != "pusher:connection_established" {
+            return Err(LabeledError::new("Pusher connection failed")
+                .with_label(format!("unexpected first message: {message}"), span));
+        }
+        let data = parse_data(&message, span)?;
+        if let Some(timeout) = data["activity_timeout"].as_u64() {
+            self.activity_timeout = Duration::from_secs(timeout);
+        }
+        let socket_id = data["socket_id"].as_str().unwrap_or_default().to_string();
+        log::debug!("Pusher connection established as {socket_id}");
+        Ok(socket_id)
+    }
+
+    fn subscribe(
+        &mut self,
+        data: serde_json::Value,
+        channel: &Spanned<String>,
+    ) -> Result<(), LabeledError> {
+        log::debug!("Subscribing to {}", channel.item);
+        self.send("pusher:subscribe", data)?;
+
+        while let Some(message) = self.next()? {
+            match message["event"].as_str() {
+                Some("pusher_internal:subscription_succeeded")
+                    if message["channel"] == channel.item =>
+                {
+                    log::debug!("Subscribed to {}", channel.item);
+                    return Ok(());
+                }
+                Some("pusher:subscription_error") if message["channel"] == channel.item => {
+                    let data = parse_data(&message, channel.span)?;
+                    return Err(LabeledError::new(format!(
+                        "Pusher subscription failed: {}",
+                        data["error"].as_str().unwrap_or("unknown error")
+                    ))
+                    .with_label(format!("status {}", data["status"]), channel.span));
+                }
+                _ => log::trace!("Ignoring message while subscribing: {message}"),
+            }
+        }
+        Err(LabeledError::new("Pusher subscription failed").with_label(
+            "connection ended before the subscription succeeded",
+            channel.span,
+        ))
+    }
+
+    /// The next message, answering pings and keeping the connection alive along the way.
+    fn next(&mut self) -> Result<Option<serde_json::Value>, LabeledError> {
+        loop {
+            let ping_at = self.last_activity + self.activity_timeout;
+            let text = match self.session.recv_until(Some(ping_at))? {
+                Received::Message(tungstenite::Message::Text(text)) => text,
+                Received::Message(_) => continue,
+                Received::Idle => {
+                    log::trace!("Connection idle, sending pusher:ping");
+                    self.send("pusher:ping", json!({}))?;
+                    self.last_activity = Instant::now();
+                    continue;
+                }
+                Received::Closed => return Ok(None),
+            };
+            self.last_activity = Instant::now();
+
+            let message = parse_json(&text, self.session.span())?;
+            match message["event"].as_str() {
+                Some("pusher:ping") => {
+                    log::trace!("Answering pusher:ping");
+                    self.send("pusher:pong", json!({}))?;
+                }
+                Some("pusher:pong") => {}
+                Some("pusher:error") => {
+                    let data = parse_data(&message, self.session.span())?;
+                    return Err(LabeledError::new(format!(
+                        "Pusher error {}: {}",
+                        data["code"],
+                        data["message"].as_str().unwrap_or("unknown error")
+                    ))
+                    .with_label("reported by the Pusher server", self.session.span()));
+                }
+                _ => return Ok(Some(message)),
+            }
+        }
+    }
+}
+
+/// Protocol messages carry their `data` as a JSON-encoded string.
+fn parse_data(message: &serde_json::Value, span: Span) -> Result<serde_json::Value, LabeledError> {
+    match &message["data"] {
+        serde_json::Value::String(text) => parse_json(text, span),
+        data => Ok(data.clone()),
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use tungstenite::Message;
+
+use super::{init_logging, listen, resolve_path, serve_values, server_flags, speed};
+use crate::{
+    ws::{
+        client::{is_read_timeout, POLL_INTERVAL},
+        recording::{self, Direction, RecordedFrame},
+        server::{accept, spawn_accept_loop, ServerStream, Stop},
+    },
+    WebSocketPlugin,
+};
+
+const DEFAULT_PORT: u16 = 9001;
+
+/// How long to wait for a client to answer the close frame at the end of a replay.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct WebSocketReplay;
+
+impl PluginCommand for WebSocketReplay {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws replay"
+    }
+
+    fn description(&self) -> &str {
+        "serve a recording made with `ws --record` to connecting clients"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Each client that connects to ws://<bind>:<port>/ is played the recording from the \
+         start: frames the server sent are sent with their original timing, divided by --speed, \
+         and before going past a frame the client sent, the server waits for the client to send \
+         its next message. Every client message is output as {connection, message, expected, \
+         matches}. The connection is closed once the recording ends. Serving stops after \
+         --max-time or when interrupted."
+    }
+
+    fn signature(&self) -> Signature {
+        server_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+                .required(
+                    "file",
+                    SyntaxShape::Filepath,
+                    "The recording, as written by `ws --record`.",
+                ),
+            DEFAULT_PORT,
+        )
+        .named(
+            "speed",
+            SyntaxShape::Any,
+            "how much faster than recorded to play, e.g. 10 or 10x (default 1)",
+            None,
+        )
+        .named(
+            "max-time",
+            SyntaxShape::Duration,
+            "stop serving after this long",
+            Some('m'),
+        )
+        .named(
+            "verbose",
+            SyntaxShape::Int,
+            "verbosity level (0=error, 1=warn, 2=info, 3=debug, 4=trace)",
+            Some('v'),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        init_logging(call)?;
+        let head = call.head;
+
+        let file: Spanned<String> = call.req(0)?;
+        let frames = recording::load(&resolve_path(engine, &file)?).map_err(|e| {
+            LabeledError::new(format!("Could not read the recording: {e}"))
+                .with_label("while reading this file", file.span)
+        })?;
+        let speed = speed(call)?;
+
+        let listener = listen(call, DEFAULT_PORT)?;
+        if let Ok(address) = listener.local_addr() {
+            log::info!("Replaying {} frames on {address}", frames.len());
+        }
+
+        let stop = Stop::default();
+        let player = Arc::new(Player {
+            frames,
+            speed,
+            stop: stop.clone(),
+            next_connection: AtomicU64::new(1),
+            span: head,
+        });
+        let (tx, rx) = mpsc::channel();
+        let stop = spawn_accept_loop(listener, stop, move |stream, peer| {
+            player.serve(stream, peer, tx.clone())
+        });
+        serve_values(call, engine, rx, stop)
+    }
+}
+
+struct Player {
+    frames: Vec<RecordedFrame>,
+    speed: f64,
+    stop: Stop,
+    next_connection: AtomicU64,
+    span: Span,
+}
+
+impl Player {
+    fn stopped(&self) -> bool {
+        self.stop.is_set()
+    }
+
+    fn serve(&self, stream: TcpStream, peer: SocketAddr, tx: Sender<Value>) {
+        let mut socket = match accept(stream, &[]) {
+            Ok(accepted) => accepted.socket,
+            Err(e) => {
+                log::warn!("Handshake with {peer} failed: {e}");
+                return;
+            }
+        };
+        let connection = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        log::debug!("Replaying to {peer} as connection {connection}");
+
+        match self.play(&mut socket, connection, &tx) {
+            Ok(()) => log::debug!("Finished replaying to {peer}"),
+            Err(e) => log::debug!("Stopped replaying to {peer}: {e}"),
+        }
+        // Wait for the client to answer the close frame
+        let _ = socket.close(None);
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while Instant::now() < deadline {
+            match socket.read() {
+                Ok(_) => {}
+                Err(e) if is_read_timeout(&e) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Plays the recording to one client, stopping early if it goes away.
+    #[allow(clippy::result_large_err)]
+    fn play(
+        &self,
+        socket: &mut ServerStream,
+        connection: u64,
+        tx: &Sender<Value>,
+    ) -> tungstenite::Result<()> {
+        let mut previous = Duration::ZERO;
+        let mut since = Instant::now();
+
+        for frame in &self.frames {
+            if self.stopped() {
+                return Ok(());
+            }
+            match frame.direction {
+                Direction::Received => {
+                    let delay = frame.time.saturating_sub(previous).as_secs_f64() / self.speed;
+                    // A delay too long for an Instant is never over, so only a stop ends it
+                    let due = Duration::try_from_secs_f64(delay)
+                        .ok()
+                        .and_then(|delay| since.checked_add(delay));
+                    while due.is_none_or(|due| Instant::now() < due) {
+                        if self.stopped() {
+                            return Ok(());
+                        }
+                        let left = due.map_or(POLL_INTERVAL, |due| due - Instant::now());
+                        thread::sleep(left.min(POLL_INTERVAL));
+                    }
+                    since = due.expect("the wait only ends once the frame is due");
+                    log::trace!("Replaying {:?}", frame.message);
+                    if let Message::Close(close) = &frame.message {
+                        socket.close(close.clone())?;
+                        return Ok(());
+                    }
+                    socket.send(frame.message.clone())?;
+                }
+                Direction::Sent => {
+                    let Some(message) = self.client_message(socket)? else {
+                        return Ok(());
+                    };
+                    if message.is_close() {
+                        log::debug!("The client closed the connection");
+                        return Ok(());
+                    }
+                    let record = self.message_record(connection, message, &frame.message);
+                    if tx.send(record).is_err() {
+                        return Ok(());
+                    }
+                    since = Instant::now();
+                }
+            }
+            previous = frame.time;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next Text, Binary or Close frame from the client.
+    #[allow(clippy::result_large_err)]
+    fn client_message(&self, socket: &mut ServerStream) -> tungstenite::Result<Option<Message>> {
+        loop {
+            if self.stopped() {
+                return Ok(None);
+            }
+            match socket.read() {
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) | Ok(Message::Frame(_)) => continue,
+                Ok(message) => return Ok(Some(message)),
+                Err(e) if is_read_timeout(&e) => continue,
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn message_record(&self, connection: u64, message: Message, expected: &Message) -> Value {
+        let span = self.span;
+        let matches = &message == expected;
+        let mut record = Record::new();
+        record.push("connection", Value::int(connection as i64, span));
+        record.push("message", message_value(message, span));
+        record.push("expected", message_value(expected.clone(), span));
+        record.push("matches", Value::bool(matches, span));
+        Value::record(record, span)
+    }
+}
+
+fn message_value(message: Message, span: Span) -> Value {
+    match message {
+        Message::Text(text) => Value::string(text, span),
+        Message::Close(_) => Value::nothing(span),
+        other => Value::binary(other.into_data(), span),
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use serde_json::json;
+
+use super::{connection_flags, open_session, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::Session,
+    },
+    WebSocketPlugin,
+};
+
+pub struct WebSocketRpc;
+
+impl PluginCommand for WebSocketRpc {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws rpc"
+    }
+
+    fn description(&self) -> &str {
+        "call a JSON-RPC 2.0 method over a websocket and return its result"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Calls to methods ending in `_subscribe` (or any call with --subscribe) stream the \
+         notifications of the subscription they create. Piping a list of {method, params} \
+         records without a method argument sends them as a single batch."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::Any),
+                    (Type::list(Type::Any), Type::list(Type::Any)),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL of the JSON-RPC endpoint (ws:// or wss://).",
+                )
+                .optional("method", SyntaxShape::String, "The method to call.")
+                .optional(
+                    "params",
+                    SyntaxShape::Any,
+                    "The parameters of the call, as a list or a record.",
+                )
+                .switch(
+                    "subscribe",
+                    "stream the notifications of the subscription created by this call",
+                    Some('s'),
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let method: Option<Spanned<String>> = call.opt(1)?;
+        let params: Option<Value> = call.opt(2)?;
+        let subscribe = call.has_flag("subscribe")?;
+
+        match method {
+            Some(method) => {
+                let params = params.as_ref().map(value_to_json).transpose()?;
+                let mut client = JsonRpcClient::new(open_session(call, engine, &[])?);
+
+                let id = client.request(&method.item, params)?;
+                let result = into_result(client.response(id)?, method.span)?;
+
+                if subscribe || method.item.ends_with("_subscribe") {
+                    log::debug!("Streaming notifications for subscription {result}");
+                    let span = call.head;
+                    return Ok(stream_values(
+                        move || {
+                            Ok(client
+                                .notification(&result)?
+                                .map(|value| json_to_value(value, span)))
+                        },
+                        span,
+                        engine.signals().clone(),
+                    ));
+                }
+
+                Ok(PipelineData::Value(json_to_value(result, call.head), None))
+            }
+            None => {
+                let requests = match input {
+                    PipelineData::Value(Value::List { vals, .. }, ..) => vals,
+                    PipelineData::ListStream(stream, ..) => stream.into_iter().collect(),
+                    _ => {
+                        return Err(LabeledError::new("Missing method")
+                            .with_label("a method is required", call.head)
+                            .with_help(
+                                "pipe in a list of {method, params} records to send a batch",
+                            ))
+                    }
+                };
+                let batch = requests
+                    .iter()
+                    .map(batch_entry)
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Servers answer an empty batch with a single error that matches no request
+                if batch.is_empty() {
+                    return Err(LabeledError::new("Empty batch")
+                        .with_label("no requests to send", call.head)
+                        .with_help("pipe in a list of {method, params} records"));
+                }
+
+                let mut client = JsonRpcClient::new(open_session(call, engine, &[])?);
+                let responses = client.batch(batch)?;
+
+                Ok(PipelineData::Value(
+                    Value::list(
+                        responses
+                            .into_iter()
+                            .map(|response| batch_record(response, call.head))
+                            .collect(),
+                        call.head,
+                    ),
+                    None,
+                ))
+            }
+        }
+    }
+}
+
+/// Correlates JSON-RPC responses with the requests sent on a [`Session`].
+struct JsonRpcClient {
+    session: Session,
+    next_id: u64,
+    /// Notifications received while waiting for a response, kept for subscriptions.
+    pending: VecDeque<serde_json::Value>,
+}
+
+impl JsonRpcClient {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            next_id: 1,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn message(&mut self, method: &str, params: Option<serde_json::Value>) -> serde_json::Value {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut message = json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if let Some(params) = params {
+            message["params"] = params;
+        }
+        message
+    }
+
+    fn request(
+        &mut self,
+        method: &str,
+        params: Option<serde_json::Value>,
+    ) -> Result<u64, LabeledError> {
+        let message = self.message(method, params);
+        log::debug!("Sending JSON-RPC request: {message}");
+        self.session.send_json(&message)?;
+        Ok(message["id"].as_u64().unwrap_or_default())
+    }
+
+    /// Waits for the response to request `id`, holding on to notifications that arrive first.
+    fn response(&mut self, id: u64) -> Result<serde_json::Value, LabeledError> {
+        loop {
+            let Some(message) = self.session.recv_json()? else {
+                return Err(self.closed_before("a response"));
+            };
+            for message in unbatch(message) {
+                if message.get("id") == Some(&json!(id)) && message.get("method").is_none() {
+                    return Ok(message);
+                }
+                self.check_unattributed(&message)?;
+                self.keep(message);
+            }
+        }
+    }
+
+    /// Sends `requests` as one batch and returns their responses in the same order.
+    fn batch(
+        &mut self,
+        requests: Vec<(String, Option<serde_json::Value>)>,
+    ) -> Result<Vec<serde_json::Value>, LabeledError> {
+        let messages: Vec<_> = requests
+            .into_iter()
+            .map(|(method, params)| self.message(&method, params))
+            .collect();
+        let ids: Vec<_> = messages.iter().map(|m| m["id"].clone()).collect();
+        let mut responses: Vec<Option<serde_json::Value>> = vec![None; ids.len()];
+
+        log::debug!("Sending JSON-RPC batch of {} requests", ids.len());
+        self.session
+            .send_json(&serde_json::Value::Array(messages))?;
+
+        while responses.iter().any(Option::is_none) {
+            let Some(message) = self.session.recv_json()? else {
+                return Err(self.closed_before("all batch responses"));
+            };
+            for message in unbatch(message) {
+                let slot = message
+                    .get("id")
+                    .filter(|_| message.get("method").is_none())
+                    .and_then(|id| ids.iter().position(|i| i == id));
+                match slot {
+                    Some(slot) => responses[slot] = Some(message),
+                    None => {
+                        self.check_unattributed(&message)?;
+                        self.keep(message)
+                    }
+                }
+            }
+        }
+
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    /// Waits for the next notification of `subscription` and returns its payload.
+    fn notification(
+        &mut self,
+        subscription: &serde_json::Value,
+    ) -> Result<Option<serde_json::Value>, LabeledError> {
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => match self.session.recv_json()? {
+                    Some(message) => message,
+                    None => return Ok(None),
+                },
+            };
+            for mut message in unbatch(message) {
+                let params = message.get_mut("params");
+                if let Some(params) = params {
+                    if params.get("subscription") == Some(subscription) {
+                        return Ok(Some(params["result"].take()));
+                    }
+                }
+                log::trace!("Ignoring unrelated message: {message}");
+            }
+        }
+    }
+
+    /// Fails on an error response with a null id, which a server sends when it cannot tell
+    /// which request failed, e.g. for an invalid batch. No pending request would match it.
+    fn check_unattributed(&self, message: &serde_json::Value) -> Result<(), LabeledError> {
+        if message["id"].is_null() && message.get("error").is_some_and(|e| !e.is_null()) {
+            into_result(message.clone(), self.session.span())?;
+        }
+        Ok(())
+    }
+
+    fn keep(&mut self, message: serde_json::Value) {
+        if message.get("method").is_some() {
+            log::trace!("Holding on to notification: {message}");
+            self.pending.push_back(message);
+        } else {
+            log::debug!("Ignoring unexpected message: {message}");
+        }
+    }
+
+    fn closed_before(&self, what: &str) -> LabeledError {
+        let reason = if self.session.timed_out() {
+            "timed out"
+        } else {
+            "connection closed"
+        };
+        LabeledError::new(format!("No JSON-RPC response: {reason}")).with_label(
+            format!("{reason} before {what} arrived"),
+            self.session.span(),
+        )
+    }
+}
+
+/// Splits a batch response into its messages.
+fn unbatch(message: serde_json::Value) -> Vec<serde_json::Value> {
+    match message {
+        serde_json::Value::Array(messages) => messages,
+        message => vec![message],
+    }
+}
+
+/// Returns the `result` of a response, or its `error` as a [`LabeledError`].
+fn into_result(
+    mut response: serde_json::Value,
+    span: Span,
+) -> Result<serde_json::Value, LabeledError> {
+    if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+        let code = error["code"].as_i64().unwrap_or_default();
+        let message = error["message"].as_str().unwrap_or("unknown error");
+        let mut labeled = LabeledError::new(format!("JSON-RPC error {code}: {message}"))
+            .with_code(code.to_string())
+            .with_label(message.to_string(), span);
+        if let Some(data) = error.get("data").filter(|d| !d.is_null()) {
+            labeled = labeled.with_help(format!("error data: {data}"));
+        }
+        return Err(labeled);
+    }
+    Ok(response["result"].take())
+}
+
+fn batch_entry(value: &Value) -> Result<(String, Option<serde_json::Value>), LabeledError> {
+    let invalid = || {
+        LabeledError::new("Invalid batch request").with_label(
+            "expected a record with a `method` and optional `params`",
+            value.span(),
+        )
+    };
+    let record = value.as_record().map_err(|_| invalid())?;
+    let method = record.get("method").ok_or_else(invalid)?.coerce_string()?;
+    let params = record.get("params").map(value_to_json).transpose()?;
+    Ok((method, params))
+}
+
+fn batch_record(mut response: serde_json::Value, span: Span) -> Value {
+    let mut record = Record::new();
+    record.push("id", json_to_value(response["id"].take(), span));
+    record.push("result", json_to_value(response["result"].take(), span));
+    record.push("error", json_to_value(response["error"].take(), span));
+    Value::record(record, span)
+}
@@ -0,0 +1,410 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use serde_json::{json, Value as Json};
+use tungstenite::Message;
+use url::Url;
+
+use super::{
+    connect_options, connection_flags, cookie_jar, handshake_headers, init_logging, max_time,
+    open_session_to, stream_values,
+};
+use crate::{
+    ws::{
+        client::{http_parse_url, redact_url},
+        http,
+        json::{json_to_value, value_to_json},
+        session::{parse_json, Received, Session},
+    },
+    WebSocketPlugin,
+};
+
+/// Terminates every message of the JSON hub protocol.
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+const INVOCATION: u64 = 1;
+const COMPLETION: u64 = 3;
+const PING: u64 = 6;
+const CLOSE: u64 = 7;
+
+/// How often to ping an otherwise quiet hub; servers drop clients silent for 30 seconds.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Negotiate responses may redirect to another endpoint, e.g. Azure SignalR Service.
+const MAX_REDIRECTS: usize = 5;
+
+pub struct WebSocketSignalR;
+
+impl PluginCommand for WebSocketSignalR {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws signalr"
+    }
+
+    fn description(&self) -> &str {
+        "connect to an ASP.NET Core SignalR hub and stream the methods it invokes"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The hub URL is the http(s):// address the hub is mapped to; a negotiate request is \
+         made there first unless --skip-negotiation is given. Invocations of client methods are \
+         output as {target, arguments} records. With --invoke, the hub method is called with \
+         the remaining arguments and its completion result is returned instead."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::Any)])
+                .required(
+                    "hub-url",
+                    SyntaxShape::String,
+                    "The URL of the hub (http://, https://, ws:// or wss://).",
+                )
+                .rest("args", SyntaxShape::Any, "Arguments of the --invoke call.")
+                .named(
+                    "invoke",
+                    SyntaxShape::String,
+                    "call this hub method and return its result",
+                    Some('i'),
+                )
+                .named(
+                    "target",
+                    SyntaxShape::String,
+                    "only output invocations of this client method",
+                    Some('t'),
+                )
+                .switch(
+                    "skip-negotiation",
+                    "connect the WebSocket directly, for hubs that only allow WebSockets",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        init_logging(call)?;
+        let hub: Value = call.req(0)?;
+        let span = hub.span();
+        let invoke: Option<Spanned<String>> = call.get_flag("invoke")?;
+        let target: Option<String> = call.get_flag("target")?;
+        let args = call
+            .rest::<Value>(1)?
+            .iter()
+            .map(value_to_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        if invoke.is_none() && !args.is_empty() {
+            return Err(LabeledError::new("Unexpected arguments")
+                .with_label("arguments are only used with --invoke", call.head));
+        }
+
+        let (_, hub_url) = http_parse_url(call, span, hub)?;
+        let url = if call.has_flag("skip-negotiation")? {
+            websocket_url(&hub_url, span)?
+        } else {
+            negotiate(call, engine, &hub_url, span)?
+        };
+
+        let mut hub = HubConnection::new(open_session_to(call, engine, &url, span, &[])?);
+        hub.handshake()?;
+
+        let head = call.head;
+        if let Some(method) = invoke {
+            let result = hub.invoke(&method, args)?;
+            return Ok(PipelineData::Value(json_to_value(result, head), None));
+        }
+
+        Ok(stream_values(
+            move || {
+                while let Some(mut message) = hub.next()? {
+                    if message["type"] != json!(INVOCATION) {
+                        log::trace!("Ignoring message: {message}");
+                        continue;
+                    }
+                    let name = message["target"].as_str().unwrap_or_default().to_string();
+                    if target.as_ref().is_some_and(|target| *target != name) {
+                        log::trace!("Skipping invocation of {name}");
+                        continue;
+                    }
+                    let mut record = Record::new();
+                    record.push("target", Value::string(name, head));
+                    record.push(
+                        "arguments",
+                        json_to_value(message["arguments"].take(), head),
+                    );
+                    return Ok(Some(Value::record(record, head)));
+                }
+                Ok(None)
+            },
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// Makes the negotiate request and returns the WebSocket URL of the connection it creates.
+fn negotiate(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+    hub: &Url,
+    span: Span,
+) -> Result<Url, LabeledError> {
+    let jar = cookie_jar(call, engine)?;
+    let mut headers = handshake_headers(call, hub, jar.as_ref())?;
+    let (_, mut connector) = connect_options(call, engine, hub, span)?;
+    let timeout = max_time(call)?;
+    let mut hub = hub.clone();
+    let mut access_token = None;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let mut endpoint = hub.clone();
+        endpoint
+            .path_segments_mut()
+            .map_err(|_| invalid_hub(span))?
+            .pop_if_empty()
+            .push("negotiate");
+        endpoint
+            .query_pairs_mut()
+            .append_pair("negotiateVersion", "1");
+        if endpoint.scheme() == "ws" || endpoint.scheme() == "wss" {
+            let scheme = if endpoint.scheme() == "ws" {
+                "http"
+            } else {
+                "https"
+            };
+            endpoint.set_scheme(scheme).map_err(|_| invalid_hub(span))?;
+        }
+
+        let response = http::post(&endpoint, &headers, timeout, &mut connector).map_err(|e| {
+            LabeledError::new(format!("Negotiate request failed: {e}"))
+                .with_label("could not negotiate with this hub", span)
+        })?;
+        if response.status != 200 {
+            return Err(LabeledError::new(format!(
+                "Negotiate request failed with status {}",
+                response.status
+            ))
+            .with_label("the hub refused to negotiate", span)
+            .with_help(String::from_utf8_lossy(&response.body).into_owned()));
+        }
+        let negotiated = parse_json(&String::from_utf8_lossy(&response.body), span)?;
+        log::debug!("Negotiate response: {negotiated}");
+
+        if let Some(error) = negotiated["error"].as_str() {
+            return Err(LabeledError::new(format!("Negotiation failed: {error}"))
+                .with_label("the hub refused the connection", span));
+        }
+        if let Some(redirect) = negotiated["url"].as_str() {
+            hub = Url::parse(redirect).map_err(|_| invalid_hub(span))?;
+            log::debug!("Redirected to {}", redact_url(&hub));
+            access_token = negotiated["accessToken"].as_str().map(str::to_string);
+            if let Some(token) = &access_token {
+                headers.set("Authorization", format!("Bearer {token}"));
+            }
+            continue;
+        }
+
+        let websockets = negotiated["availableTransports"]
+            .as_array()
+            .is_some_and(|transports| {
+                transports
+                    .iter()
+                    .any(|transport| transport["transport"] == "WebSockets")
+            });
+        if !websockets {
+            return Err(LabeledError::new("WebSockets not available")
+                .with_label("this hub does not offer the WebSockets transport", span));
+        }
+
+        // Version 1 separates the secret connection token from the public connection id
+        let id = match negotiated["negotiateVersion"].as_u64() {
+            Some(version) if version >= 1 => negotiated["connectionToken"].as_str(),
+            _ => negotiated["connectionId"].as_str(),
+        }
+        .ok_or_else(|| {
+            LabeledError::new("Invalid negotiate response")
+                .with_label("the hub did not return a connection id", span)
+        })?;
+
+        let mut url = websocket_url(&hub, span)?;
+        url.query_pairs_mut().append_pair("id", id);
+        if let Some(token) = &access_token {
+            url.query_pairs_mut().append_pair("access_token", token);
+        }
+        return Ok(url);
+    }
+
+    Err(LabeledError::new("Too many negotiate redirects")
+        .with_label("the hub kept redirecting", span))
+}
+
+/// Maps an http(s):// hub URL to the ws(s):// URL of its WebSocket endpoint.
+fn websocket_url(hub: &Url, span: Span) -> Result<Url, LabeledError> {
+    let scheme = match hub.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        _ => {
+            return Err(LabeledError::new("Unsupported URL scheme")
+                .with_label("expected an http(s):// or ws(s):// URL", span))
+        }
+    };
+    let mut url = hub.clone();
+    url.set_scheme(scheme).map_err(|_| invalid_hub(span))?;
+    Ok(url)
+}
+
+fn invalid_hub(span: Span) -> LabeledError {
+    LabeledError::new("Invalid hub URL").with_label("cannot be used as a hub address", span)
+}
+
+/// A connection speaking the JSON hub protocol.
+struct HubConnection {
+    session: Session,
+    pending: VecDeque<Json>,
+    next_invocation: u64,
+    last_sent: Instant,
+}
+
+impl HubConnection {
+    fn new(session: Session) -> Self {
+        Self {
+            session,
+            pending: VecDeque::new(),
+            next_invocation: 1,
+            last_sent: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, message: &Json) -> Result<(), LabeledError> {
+        self.session
+            .send_text(format!("{message}{RECORD_SEPARATOR}"))?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Reads the next frame into `pending`, pinging the hub if it is time to.
+    ///
+    /// Returns false once the session is over.
+    fn fill(&mut self) -> Result<bool, LabeledError> {
+        loop {
+            match self
+                .session
+                .recv_until(Some(self.last_sent + KEEP_ALIVE_INTERVAL))?
+            {
+                Received::Message(Message::Text(text)) => {
+                    for record in text.split(RECORD_SEPARATOR).filter(|r| !r.is_empty()) {
+                        self.pending
+                            .push_back(parse_json(record, self.session.span())?);
+                    }
+                    return Ok(true);
+                }
+                Received::Message(other) => log::debug!("Ignoring non-text message: {other:?}"),
+                Received::Idle => self.send(&json!({ "type": PING }))?,
+                Received::Closed => return Ok(false),
+            }
+        }
+    }
+
+    fn handshake(&mut self) -> Result<(), LabeledError> {
+        self.send(&json!({ "protocol": "json", "version": 1 }))?;
+        while self.pending.is_empty() {
+            if !self.fill()? {
+                return Err(LabeledError::new("SignalR handshake failed")
+                    .with_label("the hub closed the connection", self.session.span()));
+            }
+        }
+        let response = self.pending.pop_front().unwrap_or_default();
+        if let Some(error) = response["error"].as_str() {
+            return Err(
+                LabeledError::new(format!("SignalR handshake failed: {error}"))
+                    .with_label("the hub refused the JSON protocol", self.session.span()),
+            );
+        }
+        log::debug!("Handshake completed");
+        Ok(())
+    }
+
+    /// The next hub message other than a ping, or `None` once the hub closed the connection.
+    fn next(&mut self) -> Result<Option<Json>, LabeledError> {
+        loop {
+            let Some(message) = self.pending.pop_front() else {
+                if !self.fill()? {
+                    return Ok(None);
+                }
+                continue;
+            };
+            match message["type"].as_u64() {
+                Some(PING) => {
+                    log::trace!("Answering ping");
+                    self.send(&json!({ "type": PING }))?;
+                }
+                Some(CLOSE) => {
+                    log::debug!("Hub closed the connection: {message}");
+                    return match message["error"].as_str() {
+                        Some(error) => Err(LabeledError::new(format!(
+                            "Hub closed the connection: {error}"
+                        ))
+                        .with_label("while connected to this hub", self.session.span())),
+                        None => Ok(None),
+                    };
+                }
+                _ => return Ok(Some(message)),
+            }
+        }
+    }
+
+    fn invoke(
+        &mut self,
+        method: &Spanned<String>,
+        arguments: Vec<Json>,
+    ) -> Result<Json, LabeledError> {
+        let id = self.next_invocation.to_string();
+        self.next_invocation += 1;
+        log::debug!("Invoking {} as {id}", method.item);
+        self.send(&json!({
+            "type": INVOCATION,
+            "invocationId": id,
+            "target": method.item,
+            "arguments": arguments,
+        }))?;
+
+        while let Some(mut message) = self.next()? {
+            if message["type"] != json!(COMPLETION) || message["invocationId"] != json!(id) {
+                log::trace!("Ignoring message: {message}");
+                continue;
+            }
+            if let Some(error) = message["error"].as_str() {
+                return Err(LabeledError::new(format!("Hub method failed: {error}"))
+                    .with_label("this invocation failed", method.span));
+            }
+            return Ok(message["result"].take());
+        }
+
+        let reason = if self.session.timed_out() {
+            "timed out"
+        } else {
+            "connection closed"
+        };
+        Err(
+            LabeledError::new(format!("No completion: {reason}")).with_label(
+                format!("{reason} before this invocation completed"),
+                method.span,
+            ),
+        )
+    }
+}
@@ -0,0 +1,543 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use serde_json::{json, Map, Value as Json};
+use tungstenite::Message;
+
+use super::{connection_flags, input_values, open_session, stream_values};
+use crate::{
+    ws::{
+        json::{json_to_value, value_to_json},
+        session::{parse_json, Received, Session},
+    },
+    WebSocketPlugin,
+};
+
+const SUBPROTOCOL: &str = "wamp.2.json";
+
+const HELLO: u64 = 1;
+const WELCOME: u64 = 2;
+const ABORT: u64 = 3;
+const GOODBYE: u64 = 6;
+const ERROR: u64 = 8;
+const PUBLISH: u64 = 16;
+const PUBLISHED: u64 = 17;
+const SUBSCRIBE: u64 = 32;
+const SUBSCRIBED: u64 = 33;
+const EVENT: u64 = 36;
+const CALL: u64 = 48;
+const RESULT: u64 = 50;
+
+/// How long to wait for the router to acknowledge our GOODBYE.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Adds the arguments shared by the `ws wamp` subcommands.
+fn wamp_signature(name: &str) -> Signature {
+    Signature::build(name)
+        .required(
+            "URL",
+            SyntaxShape::String,
+            "The URL of the WAMP router (ws:// or wss://).",
+        )
+        .required_named("realm", SyntaxShape::String, "the realm to join", Some('r'))
+}
+
+pub struct WebSocketWampCall;
+
+impl PluginCommand for WebSocketWampCall {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws wamp call"
+    }
+
+    fn description(&self) -> &str {
+        "call a procedure on a WAMP router and return its result"
+    }
+
+    fn extra_description(&self) -> &str {
+        "A result with a single positional value returns that value, several return a list. \
+         When the result has keyword arguments, a {args, kwargs} record is returned instead."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            wamp_signature(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::Any)])
+                .required(
+                    "procedure",
+                    SyntaxShape::String,
+                    "The URI of the procedure, e.g. com.example.add.",
+                )
+                .rest(
+                    "args",
+                    SyntaxShape::Any,
+                    "Positional arguments of the call.",
+                )
+                .named(
+                    "kwargs",
+                    SyntaxShape::Record(vec![]),
+                    "keyword arguments of the call",
+                    Some('k'),
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let procedure: Spanned<String> = call.req(1)?;
+        let args = call
+            .rest::<Value>(2)?
+            .iter()
+            .map(value_to_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        let kwargs = match call.get_flag::<Value>("kwargs")? {
+            Some(kwargs) => value_to_json(&kwargs)?,
+            None => json!({}),
+        };
+
+        let mut wamp = Wamp::join(call, engine)?;
+        let (args, kwargs) = wamp.call(&procedure, args, kwargs)?;
+
+        let head = call.head;
+        let value = match (kwargs.is_empty(), args.len()) {
+            (true, 0) => Value::nothing(head),
+            (true, 1) => json_to_value(args.into_iter().next().unwrap_or_default(), head),
+            (true, _) => json_to_value(Json::Array(args), head),
+            (false, _) => arguments_record(args, kwargs, head),
+        };
+        Ok(PipelineData::Value(value, None))
+    }
+}
+
+pub struct WebSocketWampSubscribe;
+
+impl PluginCommand for WebSocketWampSubscribe {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws wamp subscribe"
+    }
+
+    fn description(&self) -> &str {
+        "subscribe to a topic on a WAMP router and stream its events"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Events are output as {topic, args, kwargs} records. The stream ends when the router \
+         ends the session."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            wamp_signature(PluginCommand::name(self))
+                .input_output_types(vec![(Type::Nothing, Type::list(Type::record()))])
+                .required(
+                    "topic",
+                    SyntaxShape::String,
+                    "The URI of the topic, e.g. com.example.updates.",
+                )
+                .named(
+                    "match",
+                    SyntaxShape::String,
+                    "how the topic is matched: exact, prefix or wildcard",
+                    None,
+                ),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let topic: Spanned<String> = call.req(1)?;
+        let policy: Option<Spanned<String>> = call.get_flag("match")?;
+
+        let mut options = json!({});
+        if let Some(policy) = policy {
+            match policy.item.as_str() {
+                "exact" => {}
+                "prefix" | "wildcard" => options["match"] = json!(policy.item),
+                _ => {
+                    return Err(LabeledError::new("Invalid match policy")
+                        .with_label("expected exact, prefix or wildcard", policy.span))
+                }
+            }
+        }
+
+        let mut wamp = Wamp::join(call, engine)?;
+        let subscription = wamp.subscribe(&topic, options)?;
+
+        let head = call.head;
+        Ok(stream_values(
+            move || {
+                Ok(wamp.next_event(subscription)?.map(|event| {
+                    let mut record = Record::new();
+                    record.push(
+                        "topic",
+                        Value::string(
+                            event.topic.as_deref().unwrap_or(&topic.item).to_string(),
+                            head,
+                        ),
+                    );
+                    record.push("args", json_to_value(Json::Array(event.args), head));
+                    record.push("kwargs", json_to_value(Json::Object(event.kwargs), head));
+                    Value::record(record, head)
+                }))
+            },
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+pub struct WebSocketWampPublish;
+
+impl PluginCommand for WebSocketWampPublish {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws wamp publish"
+    }
+
+    fn description(&self) -> &str {
+        "publish the pipeline input to a topic on a WAMP router"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Each input value is published as the single positional argument of one event, or as \
+         keyword arguments with --kwargs. Publications are acknowledged, and their ids are \
+         returned."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            wamp_signature(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Any, Type::list(Type::Int)),
+                    (Type::list(Type::Any), Type::list(Type::Int)),
+                ])
+                .required(
+                    "topic",
+                    SyntaxShape::String,
+                    "The URI of the topic, e.g. com.example.updates.",
+                )
+                .switch("kwargs", "publish input records as keyword arguments", None),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let topic: Spanned<String> = call.req(1)?;
+        let as_kwargs = call.has_flag("kwargs")?;
+
+        let events = input_values(input)?
+            .iter()
+            .map(|value| {
+                let payload = value_to_json(value)?;
+                match (as_kwargs, payload) {
+                    (false, payload) => Ok((vec![payload], Map::new())),
+                    (true, Json::Object(kwargs)) => Ok((vec![], kwargs)),
+                    (true, _) => Err(LabeledError::new("Expected a record")
+                        .with_label("--kwargs needs record input", value.span())),
+                }
+            })
+            .collect::<Result<Vec<_>, LabeledError>>()?;
+        if events.is_empty() {
+            return Err(LabeledError::new("Nothing to publish")
+                .with_label("pipe in the values to publish", call.head));
+        }
+
+        let mut wamp = Wamp::join(call, engine)?;
+        let head = call.head;
+        let publications = events
+            .into_iter()
+            .map(|(args, kwargs)| {
+                wamp.publish(&topic, args, kwargs)
+                    .map(|id| Value::int(id as i64, head))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PipelineData::Value(Value::list(publications, head), None))
+    }
+}
+
+struct WampEvent {
+    /// Only sent by the router for prefix and wildcard subscriptions.
+    topic: Option<String>,
+    args: Vec<Json>,
+    kwargs: Map<String, Json>,
+}
+
+/// A joined WAMP session; leaves the realm with GOODBYE when dropped.
+struct Wamp {
+    session: Session,
+    next_request: u64,
+    /// Events that arrived while waiting for a reply.
+    pending: VecDeque<Json>,
+    left: bool,
+}
+
+impl Wamp {
+    /// Connects to the URL in the first positional argument and joins `--realm`.
+    fn join(call: &EvaluatedCall, engine: &EngineInterface) -> Result<Self, LabeledError> {
+        let realm: Spanned<String> = call
+            .get_flag("realm")?
+            .ok_or_else(|| LabeledError::new("Missing required flag --realm"))?;
+
+        let session = open_session(call, engine, &[SUBPROTOCOL.into()])?;
+        if session.subprotocol() != Some(SUBPROTOCOL) {
+            return Err(LabeledError::new("Not a WAMP router").with_label(
+                "the server did not accept the wamp.2.json subprotocol",
+                session.span(),
+            ));
+        }
+        let mut wamp = Self {
+            session,
+            next_request: 1,
+            pending: VecDeque::new(),
+            left: false,
+        };
+
+        let roles = json!({ "caller": {}, "subscriber": {}, "publisher": {} });
+        wamp.session
+            .send_json(&json!([HELLO, realm.item, { "roles": roles }]))?;
+        loop {
+            let Some(message) = wamp.recv()? else {
+                return Err(wamp.ended("before the realm was joined", realm.span));
+            };
+            match message[0].as_u64() {
+                Some(WELCOME) => {
+                    log::debug!("Joined realm {} as session {}", realm.item, message[1]);
+                    return Ok(wamp);
+                }
+                Some(ABORT) => {
+                    wamp.left = true;
+                    let reason = message[2].as_str().unwrap_or("unknown reason");
+                    let mut error = LabeledError::new(format!("WAMP session aborted: {reason}"))
+                        .with_label("the router refused to join this realm", realm.span);
+                    if let Some(text) = message[1]["message"].as_str() {
+                        error = error.with_help(text.to_string());
+                    }
+                    return Err(error);
+                }
+                _ => log::debug!("Ignoring message before WELCOME: {message}"),
+            }
+        }
+    }
+
+    /// The next message, which is always an array, or `None` once the session is over.
+    fn recv(&mut self) -> Result<Option<Json>, LabeledError> {
+        loop {
+            let Some(message) = self.session.recv_json()? else {
+                return Ok(None);
+            };
+            if !message.is_array() {
+                log::debug!("Ignoring non-array message: {message}");
+                continue;
+            }
+            if message[0].as_u64() == Some(GOODBYE) {
+                log::debug!("Router ended the session: {}", message[2]);
+                if !self.left {
+                    self.left = true;
+                    self.session
+                        .send_json(&json!([GOODBYE, {}, "wamp.close.goodbye_and_out"]))?;
+                }
+                return Ok(None);
+            }
+            return Ok(Some(message));
+        }
+    }
+
+    fn ended(&self, when: &str, span: Span) -> LabeledError {
+        let reason = if self.left {
+            "session ended by the router"
+        } else if self.session.timed_out() {
+            "timed out"
+        } else {
+            "connection closed"
+        };
+        LabeledError::new(format!("No WAMP reply: {reason}"))
+            .with_label(format!("{reason} {when}"), span)
+    }
+
+    /// Sends a request built from a fresh request id and waits for the `reply` to it.
+    fn request(
+        &mut self,
+        build: impl FnOnce(u64) -> Json,
+        reply: u64,
+        span: Span,
+    ) -> Result<Json, LabeledError> {
+        let id = self.next_request;
+        self.next_request += 1;
+        self.session.send_json(&build(id))?;
+
+        loop {
+            let Some(message) = self.recv()? else {
+                return Err(self.ended("before the request was answered", span));
+            };
+            let kind = message[0].as_u64();
+            if kind == Some(reply) && message[1] == json!(id) {
+                return Ok(message);
+            }
+            if kind == Some(ERROR) && message[2] == json!(id) {
+                return Err(wamp_error(&message, span));
+            }
+            if kind == Some(EVENT) {
+                self.pending.push_back(message);
+                continue;
+            }
+            log::debug!("Ignoring message: {message}");
+        }
+    }
+
+    fn call(
+        &mut self,
+        procedure: &Spanned<String>,
+        args: Vec<Json>,
+        kwargs: Json,
+    ) -> Result<(Vec<Json>, Map<String, Json>), LabeledError> {
+        log::debug!("Calling {}", procedure.item);
+        let mut result = self.request(
+            |id| json!([CALL, id, {}, procedure.item, args, kwargs]),
+            RESULT,
+            procedure.span,
+        )?;
+        Ok(arguments(&mut result, 3))
+    }
+
+    fn subscribe(&mut self, topic: &Spanned<String>, options: Json) -> Result<u64, LabeledError> {
+        let subscribed = self.request(
+            |id| json!([SUBSCRIBE, id, options, topic.item]),
+            SUBSCRIBED,
+            topic.span,
+        )?;
+        let subscription = subscribed[2].as_u64().unwrap_or_default();
+        log::debug!("Subscribed to {} as {subscription}", topic.item);
+        Ok(subscription)
+    }
+
+    fn publish(
+        &mut self,
+        topic: &Spanned<String>,
+        args: Vec<Json>,
+        kwargs: Map<String, Json>,
+    ) -> Result<u64, LabeledError> {
+        let published = self.request(
+            |id| json!([PUBLISH, id, { "acknowledge": true }, topic.item, args, kwargs]),
+            PUBLISHED,
+            topic.span,
+        )?;
+        Ok(published[2].as_u64().unwrap_or_default())
+    }
+
+    /// The next event of `subscription`, or `None` once the session is over.
+    fn next_event(&mut self, subscription: u64) -> Result<Option<WampEvent>, LabeledError> {
+        loop {
+            let mut message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => match self.recv()? {
+                    Some(message) => message,
+                    None => return Ok(None),
+                },
+            };
+            if message[0].as_u64() != Some(EVENT) || message[1].as_u64() != Some(subscription) {
+                log::debug!("Ignoring message: {message}");
+                continue;
+            }
+            let topic = message[3]["topic"].as_str().map(str::to_string);
+            let (args, kwargs) = arguments(&mut message, 4);
+            return Ok(Some(WampEvent {
+                topic,
+                args,
+                kwargs,
+            }));
+        }
+    }
+}
+
+impl Drop for Wamp {
+    fn drop(&mut self) {
+        if self.left {
+            return;
+        }
+        self.left = true;
+        log::debug!("Leaving realm");
+        let goodbye = json!([GOODBYE, {}, "wamp.close.close_realm"]);
+        if self.session.send_json(&goodbye).is_err() {
+            return;
+        }
+        // The router answers with its own GOODBYE before closing the connection
+        let until = Instant::now() + GOODBYE_TIMEOUT;
+        while let Ok(Received::Message(Message::Text(text))) = self.session.recv_until(Some(until))
+        {
+            if let Ok(reply) = parse_json(&text, self.session.span()) {
+                if reply[0] == json!(GOODBYE) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Takes the optional `Arguments|list, ArgumentsKw|dict` that start at `index` of a message.
+fn arguments(message: &mut Json, index: usize) -> (Vec<Json>, Map<String, Json>) {
+    let mut take = |i: usize| message.get_mut(i).map(Json::take).unwrap_or_default();
+    let args = match take(index) {
+        Json::Array(args) => args,
+        _ => vec![],
+    };
+    let kwargs = match take(index + 1) {
+        Json::Object(kwargs) => kwargs,
+        _ => Map::new(),
+    };
+    (args, kwargs)
+}
+
+fn arguments_record(args: Vec<Json>, kwargs: Map<String, Json>, span: Span) -> Value {
+    let mut record = Record::new();
+    record.push("args", json_to_value(Json::Array(args), span));
+    record.push("kwargs", json_to_value(Json::Object(kwargs), span));
+    Value::record(record, span)
+}
+
+/// Turns `[ERROR, type, request, details, uri, args, kwargs]` into an error.
+fn wamp_error(message: &Json, span: Span) -> LabeledError {
+    let uri = message[4].as_str().unwrap_or("wamp.error.unknown");
+    let mut error =
+        LabeledError::new(format!("WAMP error: {uri}")).with_label("the router refused this", span);
+    let mut details = message.clone();
+    let (args, kwargs) = arguments(&mut details, 5);
+    let help = match (args.first(), kwargs.is_empty()) {
+        (Some(Json::String(text)), _) => Some(text.clone()),
+        (Some(arg), _) => Some(arg.to_string()),
+        (None, false) => Some(Json::Object(kwargs).to_string()),
+        (None, true) => None,
+    };
+    if let Some(help) = help {
+        error = error.with_help(help);
+    }
+    error
+}
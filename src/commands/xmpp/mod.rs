@@ -0,0 +1,519 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, PipelineData, Record, Signature, Span, Spanned, SyntaxShape, Type,
+    Value,
+};
+use tungstenite::Message;
+
+use super::{connection_flags, input_values, open_session, stream_values};
+use crate::{ws::session::Session, WebSocketPlugin};
+
+mod sasl;
+mod xml;
+
+use sasl::Scram;
+use xml::{escape, Element};
+
+const SUBPROTOCOL: &str = "xmpp";
+
+const FRAMING_NS: &str = "urn:ietf:params:xml:ns:xmpp-framing";
+const CLIENT_NS: &str = "jabber:client";
+const SASL_NS: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const BIND_NS: &str = "urn:ietf:params:xml:ns:xmpp-bind";
+const SESSION_NS: &str = "urn:ietf:params:xml:ns:xmpp-session";
+const STANZAS_NS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+
+pub struct WebSocketXmpp;
+
+impl PluginCommand for WebSocketXmpp {
+    type Plugin = WebSocketPlugin;
+
+    fn name(&self) -> &str {
+        "ws xmpp"
+    }
+
+    fn description(&self) -> &str {
+        "log in to an XMPP server over WebSocket and stream the messages sent to the account"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Speaks RFC 7395 framing with the xmpp subprotocol. Authenticates with SCRAM-SHA-1 \
+         when the server offers it and PLAIN otherwise, binds the resource given in --jid (or \
+         one the server picks) and sends initial presence. Records piped in as \
+         {to, body, type} are sent as messages first. Incoming messages with a body are output \
+         as {from, to, type, body} records."
+    }
+
+    fn signature(&self) -> Signature {
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::list(Type::record())),
+                    (Type::record(), Type::list(Type::record())),
+                    (Type::list(Type::record()), Type::list(Type::record())),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The XMPP WebSocket endpoint (ws:// or wss://).",
+                )
+                .required_named(
+                    "jid",
+                    SyntaxShape::String,
+                    "the account to log in as, e.g. alice@example.com/laptop",
+                    Some('j'),
+                )
+                .named(
+                    "mechanism",
+                    SyntaxShape::String,
+                    "use this SASL mechanism (SCRAM-SHA-1 or PLAIN)",
+                    None,
+                )
+                .switch("no-presence", "do not send initial presence", None),
+        )
+        .category(Category::Network)
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let jid: Spanned<String> = call
+            .get_flag("jid")?
+            .ok_or_else(|| LabeledError::new("Missing required flag --jid"))?;
+        let password: String = call
+            .get_flag("password")?
+            .ok_or_else(|| LabeledError::new("Missing required flag --password"))?;
+        let mechanism: Option<Spanned<String>> = call.get_flag("mechanism")?;
+        let presence = !call.has_flag("no-presence")?;
+
+        let account = Jid::parse(&jid)?;
+        let outgoing = input_values(input)?
+            .iter()
+            .map(outgoing_message)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let session = open_session(call, engine, &[SUBPROTOCOL.into()])?;
+        if session.subprotocol() != Some(SUBPROTOCOL) {
+            return Err(LabeledError::new("Not an XMPP endpoint").with_label(
+                "the server did not accept the xmpp subprotocol",
+                session.span(),
+            ));
+        }
+        let mut client = XmppClient::new(session, account.domain.clone());
+
+        let features = client.open()?;
+        client.authenticate(&features, &account, &password, mechanism, jid.span)?;
+        let features = client.open()?;
+        let bound = client.bind(&features, account.resource.as_deref(), jid.span)?;
+        log::info!("Logged in as {bound}");
+
+        if presence {
+            client.send(&format!(r#"<presence xmlns="{CLIENT_NS}"/>"#))?;
+        }
+        for message in outgoing {
+            client.send(&message)?;
+        }
+
+        let head = call.head;
+        Ok(stream_values(
+            move || client.next_message(head),
+            head,
+            engine.signals().clone(),
+        ))
+    }
+}
+
+/// A bare or full JID: `local@domain[/resource]`.
+struct Jid {
+    local: String,
+    domain: String,
+    resource: Option<String>,
+}
+
+impl Jid {
+    fn parse(jid: &Spanned<String>) -> Result<Self, LabeledError> {
+        let (bare, resource) = match jid.item.split_once('/') {
+            Some((bare, resource)) => (bare, Some(resource.to_string())),
+            None => (jid.item.as_str(), None),
+        };
+        let (local, domain) = bare
+            .split_once('@')
+            .filter(|(local, domain)| !local.is_empty() && !domain.is_empty())
+            .ok_or_else(|| {
+                LabeledError::new("Invalid JID")
+                    .with_label("expected user@domain or user@domain/resource", jid.span)
+            })?;
+        Ok(Self {
+            local: local.to_string(),
+            domain: domain.to_string(),
+            resource,
+        })
+    }
+}
+
+struct XmppClient {
+    session: Session,
+    domain: String,
+    next_id: u64,
+    closed: bool,
+}
+
+impl XmppClient {
+    fn new(session: Session, domain: String) -> Self {
+        Self {
+            session,
+            domain,
+            next_id: 1,
+            closed: false,
+        }
+    }
+
+    fn send(&mut self, xml: &str) -> Result<(), LabeledError> {
+        self.session.send_text(xml)
+    }
+
+    /// Sends a SASL `<auth/>` or `<response/>`, which must not end up in the logs.
+    fn send_sasl(&mut self, xml: &str) -> Result<(), LabeledError> {
+        self.session.send_credentials(xml)
+    }
+
+    fn id(&mut self, prefix: &str) -> String {
+        let id = format!("{prefix}{}", self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// The next top-level element, or `None` once the server closed the stream.
+    fn recv(&mut self) -> Result<Option<Element>, LabeledError> {
+        if self.closed {
+            return Ok(None);
+        }
+        while let Some(message) = self.session.recv()? {
+            let Message::Text(text) = message else {
+                log::debug!("Ignoring non-text message: {message:?}");
+                continue;
+            };
+            let element = Element::parse(&text).map_err(|e| {
+                LabeledError::new(format!("Received invalid XML: {e}"))
+                    .with_label("from this endpoint", self.session.span())
+                    .with_help(format!("message was: {text}"))
+            })?;
+            log::trace!("Received <{}/>", element.name);
+            match element.name.as_str() {
+                "close" => {
+                    log::debug!("Server closed the stream");
+                    self.closed = true;
+                    return Ok(None);
+                }
+                // Stanza errors are children of stanzas, so a top-level error is a stream error
+                "error" => {
+                    let (condition, text) = error_condition(&element);
+                    let mut error = LabeledError::new(format!("XMPP stream error: {condition}"))
+                        .with_label("the server ended the stream", self.session.span());
+                    if let Some(text) = text {
+                        error = error.with_help(text);
+                    }
+                    return Err(error);
+                }
+                _ => return Ok(Some(element)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn ended(&self, when: &str) -> LabeledError {
+        let reason = if self.session.timed_out() {
+            "timed out"
+        } else {
+            "connection closed"
+        };
+        LabeledError::new(format!("XMPP stream ended: {reason}"))
+            .with_label(format!("{reason} {when}"), self.session.span())
+    }
+
+    /// Opens (or, after authentication, restarts) the stream and returns its features.
+    fn open(&mut self) -> Result<Element, LabeledError> {
+        let open = format!(
+            r#"<open xmlns="{FRAMING_NS}" to="{}" version="1.0"/>"#,
+            escape(&self.domain)
+        );
+        self.send(&open)?;
+        while let Some(element) = self.recv()? {
+            match element.name.as_str() {
+                "open" => log::debug!("Stream opened with id {:?}", element.attr("id")),
+                "features" => return Ok(element),
+                other => log::debug!("Ignoring <{other}/> before the stream features"),
+            }
+        }
+        Err(self.ended("before the stream features arrived"))
+    }
+
+    fn authenticate(
+        &mut self,
+        features: &Element,
+        account: &Jid,
+        password: &str,
+        forced: Option<Spanned<String>>,
+        span: Span,
+    ) -> Result<(), LabeledError> {
+        let offered: Vec<&str> = features
+            .child("mechanisms")
+            .map(|mechanisms| {
+                mechanisms
+                    .children
+                    .iter()
+                    .filter(|child| child.name == "mechanism")
+                    .map(|child| child.text.trim())
+                    .collect()
+            })
+            .unwrap_or_default();
+        log::debug!("Server offers SASL mechanisms {offered:?}");
+
+        let (mechanism, span) = match forced {
+            Some(forced) if offered.contains(&forced.item.as_str()) => (forced.item, forced.span),
+            Some(forced) => {
+                return Err(LabeledError::new("SASL mechanism not offered")
+                    .with_label("the server does not offer this mechanism", forced.span)
+                    .with_help(format!("offered: {}", offered.join(", "))))
+            }
+            None => match ["SCRAM-SHA-1", "PLAIN"]
+                .into_iter()
+                .find(|mechanism| offered.contains(mechanism))
+            {
+                Some(mechanism) => (mechanism.to_string(), span),
+                None => {
+                    return Err(LabeledError::new("No supported SASL mechanism")
+                        .with_label("cannot log in to this server", self.session.span())
+                        .with_help(format!("offered: {}", offered.join(", "))))
+                }
+            },
+        };
+
+        let auth = |initial: String| {
+            format!(r#"<auth xmlns="{SASL_NS}" mechanism="{mechanism}">{initial}</auth>"#)
+        };
+        let protocol_error = |e: String| {
+            LabeledError::new(format!("Authentication failed: {e}"))
+                .with_label(format!("while logging in with {mechanism}"), span)
+        };
+
+        match mechanism.as_str() {
+            "SCRAM-SHA-1" => {
+                let mut scram = Scram::new(&account.local, password);
+                self.send_sasl(&auth(scram.client_first()))?;
+                loop {
+                    let reply = self.sasl_reply(span)?;
+                    match reply.name.as_str() {
+                        "challenge" => {
+                            let response =
+                                scram.client_final(&reply.text).map_err(protocol_error)?;
+                            self.send_sasl(&format!(
+                                r#"<response xmlns="{SASL_NS}">{response}</response>"#
+                            ))?;
+                        }
+                        _ => {
+                            scram.verify(&reply.text).map_err(protocol_error)?;
+                            break;
+                        }
+                    }
+                }
+            }
+            "PLAIN" => {
+                self.send_sasl(&auth(sasl::plain(&account.local, password)))?;
+                if self.sasl_reply(span)?.name != "success" {
+                    return Err(protocol_error("unexpected challenge".into()));
+                }
+            }
+            _ => {
+                return Err(LabeledError::new("Unsupported SASL mechanism")
+                    .with_label("expected SCRAM-SHA-1 or PLAIN", span))
+            }
+        }
+        log::debug!("Authenticated with {mechanism}");
+        Ok(())
+    }
+
+    /// Waits for a `<challenge/>` or `<success/>`, turning `<failure/>` into an error.
+    fn sasl_reply(&mut self, span: Span) -> Result<Element, LabeledError> {
+        while let Some(element) = self.recv()? {
+            match element.name.as_str() {
+                "challenge" | "success" => return Ok(element),
+                "failure" => {
+                    let (condition, text) = error_condition(&element);
+                    let mut error =
+                        LabeledError::new(format!("Authentication failed: {condition}"))
+                            .with_label("the server rejected these credentials", span);
+                    if let Some(text) = text {
+                        error = error.with_help(text);
+                    }
+                    return Err(error);
+                }
+                other => log::debug!("Ignoring <{other}/> during authentication"),
+            }
+        }
+        Err(self.ended("during authentication"))
+    }
+
+    /// Binds a resource and returns the full JID the server assigned.
+    fn bind(
+        &mut self,
+        features: &Element,
+        resource: Option<&str>,
+        span: Span,
+    ) -> Result<String, LabeledError> {
+        if features.child("bind").is_none() {
+            return Err(LabeledError::new("Resource binding not offered")
+                .with_label("the server did not offer to bind a resource", span));
+        }
+        let id = self.id("bind");
+        let resource = resource
+            .map(|resource| format!("<resource>{}</resource>", escape(resource)))
+            .unwrap_or_default();
+        self.send(&format!(
+            r#"<iq xmlns="{CLIENT_NS}" type="set" id="{id}"><bind xmlns="{BIND_NS}">{resource}</bind></iq>"#
+        ))?;
+        let result = self.iq_result(&id, span)?;
+        let jid = result
+            .child("bind")
+            .and_then(|bind| bind.child("jid"))
+            .map(|jid| jid.text.trim().to_string())
+            .ok_or_else(|| {
+                LabeledError::new("Invalid bind result")
+                    .with_label("the server did not say which resource was bound", span)
+            })?;
+
+        // Servers following RFC 3921 still require a session, unless they mark it optional
+        if features
+            .child("session")
+            .is_some_and(|session| session.child("optional").is_none())
+        {
+            let id = self.id("session");
+            self.send(&format!(
+                r#"<iq xmlns="{CLIENT_NS}" type="set" id="{id}"><session xmlns="{SESSION_NS}"/></iq>"#
+            ))?;
+            self.iq_result(&id, span)?;
+        }
+        Ok(jid)
+    }
+
+    /// Waits for the reply to the iq with `id`, turning an error reply into an error.
+    fn iq_result(&mut self, id: &str, span: Span) -> Result<Element, LabeledError> {
+        while let Some(stanza) = self.recv()? {
+            if stanza.name != "iq" || stanza.attr("id") != Some(id) {
+                self.answer_iq(&stanza)?;
+                continue;
+            }
+            if stanza.attr("type") == Some("result") {
+                return Ok(stanza);
+            }
+            let (condition, text) = stanza
+                .child("error")
+                .map(error_condition)
+                .unwrap_or(("unknown-error".into(), None));
+            let mut error = LabeledError::new(format!("XMPP request failed: {condition}"))
+                .with_label("the server refused this request", span);
+            if let Some(text) = text {
+                error = error.with_help(text);
+            }
+            return Err(error);
+        }
+        Err(self.ended("before the server replied"))
+    }
+
+    /// Replies to pings, and refuses other requests as RFC 6120 requires of every client.
+    fn answer_iq(&mut self, stanza: &Element) -> Result<(), LabeledError> {
+        if stanza.name != "iq" || !matches!(stanza.attr("type"), Some("get") | Some("set")) {
+            log::trace!("Ignoring <{}/> stanza", stanza.name);
+            return Ok(());
+        }
+        let id = escape(stanza.attr("id").unwrap_or_default());
+        let to = stanza
+            .attr("from")
+            .map(|from| format!(r#" to="{}""#, escape(from)))
+            .unwrap_or_default();
+        let reply = if stanza.child("ping").is_some() {
+            log::debug!("Answering ping {id}");
+            format!(r#"<iq xmlns="{CLIENT_NS}" type="result" id="{id}"{to}/>"#)
+        } else {
+            log::debug!("Refusing request {id}");
+            format!(
+                r#"<iq xmlns="{CLIENT_NS}" type="error" id="{id}"{to}><error type="cancel"><service-unavailable xmlns="{STANZAS_NS}"/></error></iq>"#
+            )
+        };
+        self.send(&reply)
+    }
+
+    /// The next incoming message with a body, as a record.
+    fn next_message(&mut self, span: Span) -> Result<Option<Value>, LabeledError> {
+        while let Some(stanza) = self.recv()? {
+            if stanza.name != "message" {
+                self.answer_iq(&stanza)?;
+                continue;
+            }
+            let Some(body) = stanza.child("body") else {
+                log::trace!("Skipping message without a body");
+                continue;
+            };
+            let attr = |name: &str| match stanza.attr(name) {
+                Some(value) => Value::string(value, span),
+                None => Value::nothing(span),
+            };
+            let mut record = Record::new();
+            record.push("from", attr("from"));
+            record.push("to", attr("to"));
+            record.push(
+                "type",
+                Value::string(stanza.attr("type").unwrap_or("normal"), span),
+            );
+            record.push("body", Value::string(&body.text, span));
+            return Ok(Some(Value::record(record, span)));
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for XmppClient {
+    fn drop(&mut self) {
+        if !self.closed {
+            log::debug!("Closing XMPP stream");
+            let _ = self.send(&format!(r#"<close xmlns="{FRAMING_NS}"/>"#));
+        }
+    }
+}
+
+/// The defined condition of a stream, stanza or SASL error and its optional text.
+fn error_condition(error: &Element) -> (String, Option<String>) {
+    let condition = error
+        .children
+        .iter()
+        .find(|child| child.name != "text")
+        .map(|child| child.name.clone())
+        .unwrap_or_else(|| "undefined-condition".into());
+    let text = error.child("text").map(|text| text.text.clone());
+    (condition, text)
+}
+
+/// Serializes a `{to, body, type}` record as a message stanza.
+fn outgoing_message(value: &Value) -> Result<String, LabeledError> {
+    let invalid = || {
+        LabeledError::new("Invalid message")
+            .with_label("expected a record with `to` and `body`", value.span())
+    };
+    let record = value.as_record().map_err(|_| invalid())?;
+    let field = |name: &str| -> Result<Option<String>, LabeledError> {
+        Ok(record.get(name).map(Value::coerce_string).transpose()?)
+    };
+    let to = field("to")?.ok_or_else(invalid)?;
+    let body = field("body")?.ok_or_else(invalid)?;
+    let kind = field("type")?.unwrap_or_else(|| "chat".into());
+    Ok(format!(
+        r#"<message xmlns="{CLIENT_NS}" to="{}" type="{}"><body>{}</body></message>"#,
+        escape(&to),
+        escape(&kind),
+        escape(&body)
+    ))
+}
@@ -0,0 +1,126 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+
+/// The most PBKDF2 iterations a server may ask for. Servers use a few thousand, and each one
+/// costs the client an HMAC, so a hostile server could otherwise keep it busy for hours.
+const MAX_ITERATIONS: u32 = 100_000;
+
+/// The GS2 header for a client that neither supports nor requests channel binding.
+const GS2_HEADER: &str = "n,,";
+
+/// The initial response of SASL PLAIN (RFC 4616), with no authorization identity.
+pub fn plain(user: &str, password: &str) -> String {
+    BASE64.encode(format!("\0{user}\0{password}"))
+}
+
+/// Client side of SCRAM-SHA-1 (RFC 5802), without channel binding.
+pub struct Scram {
+    password: String,
+    client_first_bare: String,
+    nonce: String,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl Scram {
+    pub fn new(user: &str, password: &str) -> Self {
+        let mut nonce = [0; 18];
+        getrandom::getrandom(&mut nonce).expect("Could not get randomness from the OS");
+        let nonce = BASE64.encode(nonce);
+        let user = user.replace('=', "=3D").replace(',', "=2C");
+        Self {
+            password: password.to_string(),
+            client_first_bare: format!("n={user},r={nonce}"),
+            nonce,
+            server_signature: None,
+        }
+    }
+
+    /// The base64 initial response sent with `<auth/>`.
+    pub fn client_first(&self) -> String {
+        BASE64.encode(format!("{GS2_HEADER}{}", self.client_first_bare))
+    }
+
+    /// Answers the base64 server-first challenge with the base64 client-final message.
+    pub fn client_final(&mut self, challenge: &str) -> Result<String, String> {
+        let server_first = decode(challenge)?;
+        let field = |name: &str| {
+            server_first
+                .split(',')
+                .find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+                .ok_or_else(|| format!("challenge has no {name} attribute"))
+        };
+        let nonce = field("r")?;
+        if !nonce.starts_with(&self.nonce) {
+            return Err("server nonce does not extend ours".into());
+        }
+        let salt = BASE64
+            .decode(field("s")?)
+            .map_err(|e| format!("invalid salt: {e}"))?;
+        let iterations: u32 = field("i")?
+            .parse()
+            .map_err(|e| format!("invalid iteration count: {e}"))?;
+        if !(1..=MAX_ITERATIONS).contains(&iterations) {
+            return Err(format!(
+                "iteration count {iterations} is outside 1 to {MAX_ITERATIONS}"
+            ));
+        }
+
+        let salted_password = hi(self.password.as_bytes(), &salt, iterations);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let stored_key = Sha1::digest(&client_key);
+
+        let without_proof = format!("c={},r={nonce}", BASE64.encode(GS2_HEADER));
+        let auth_message = format!("{},{server_first},{without_proof}", self.client_first_bare);
+        let client_signature = hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature)
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+
+        let server_key = hmac(&salted_password, b"Server Key");
+        self.server_signature = Some(hmac(&server_key, auth_message.as_bytes()));
+
+        Ok(BASE64.encode(format!("{without_proof},p={}", BASE64.encode(proof))))
+    }
+
+    /// Checks the base64 server-final message that comes with `<success/>`, proving the
+    /// server knows the password too.
+    pub fn verify(&self, outcome: &str) -> Result<(), String> {
+        let server_final = decode(outcome)?;
+        let expected = self
+            .server_signature
+            .as_ref()
+            .ok_or("the server skipped the challenge")?;
+        match server_final.strip_prefix("v=") {
+            Some(signature) if BASE64.decode(signature).ok().as_ref() == Some(expected) => Ok(()),
+            Some(_) => Err("server signature does not match".into()),
+            None => Err(format!("unexpected server-final message: {server_final}")),
+        }
+    }
+}
+
+fn decode(data: &str) -> Result<String, String> {
+    let bytes = BASE64
+        .decode(data.trim())
+        .map_err(|e| format!("invalid base64: {e}"))?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `Hi()` from RFC 5802, i.e. PBKDF2 with HMAC-SHA-1 and a single output block.
+fn hi(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut block = hmac(password, &[salt, &1u32.to_be_bytes()].concat());
+    let mut result = block.clone();
+    for _ in 1..iterations {
+        block = hmac(password, &block);
+        result.iter_mut().zip(&block).for_each(|(r, b)| *r ^= b);
+    }
+    result
+}
@@ -0,0 +1,85 @@
+use quick_xml::{events::Event, Reader};
+
+pub use quick_xml::escape::escape;
+
+/// A parsed XML element. RFC 7395 puts exactly one element in each WebSocket frame.
+///
+/// Names are local, without their namespace prefix; `xmlns` declarations are kept as
+/// ordinary attributes.
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn parse(xml: &str) -> Result<Self, String> {
+        let mut reader = Reader::from_str(xml);
+        let mut open: Vec<Element> = vec![];
+
+        loop {
+            let event = reader.read_event().map_err(|e| e.to_string())?;
+            match event {
+                Event::Start(ref start) | Event::Empty(ref start) => {
+                    let mut element = Element {
+                        name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+                        ..Default::default()
+                    };
+                    for attribute in start.attributes() {
+                        let attribute = attribute.map_err(|e| e.to_string())?;
+                        element.attributes.push((
+                            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                            attribute
+                                .unescape_value()
+                                .map_err(|e| e.to_string())?
+                                .into_owned(),
+                        ));
+                    }
+                    if matches!(event, Event::Start(_)) {
+                        open.push(element);
+                    } else if let Some(parent) = open.last_mut() {
+                        parent.children.push(element);
+                    } else {
+                        return Ok(element);
+                    }
+                }
+                Event::End(_) => {
+                    let element = open.pop().ok_or("unbalanced closing tag")?;
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(element) = open.last_mut() {
+                        element
+                            .text
+                            .push_str(&text.unescape().map_err(|e| e.to_string())?);
+                    }
+                }
+                Event::CData(data) => {
+                    if let Some(element) = open.last_mut() {
+                        element
+                            .text
+                            .push_str(&data.decode().map_err(|e| e.to_string())?);
+                    }
+                }
+                Event::Eof => return Err("no complete element".into()),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
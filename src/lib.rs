@@ -1,13 +1,22 @@
-use std::time::Duration;
+use std::sync::Arc;
 
 use nu_plugin::{EngineInterface, EvaluatedCall, Plugin, PluginCommand};
 use nu_protocol::{
-    ByteStream, ByteStreamType, Category, LabeledError, PipelineData, Signature, SyntaxShape, Type,
-    Value,
+    ast::CellPath, ByteStream, ByteStreamType, Category, LabeledError, PipelineData, Signature,
+    Spanned, SyntaxShape, Type, Value,
 };
 
+pub mod commands;
 pub mod ws;
-use ws::client::{connect, http_parse_url, request_headers};
+use commands::{
+    connect_options, connection_flags, cookie_jar, framing, handshake_headers, init_logging,
+    k8s::run_k8s_channels, max_time, resolve_path, save_cookies,
+};
+use ws::{
+    client::{connect, http_parse_url, redact_url, OutputOptions},
+    framing::Framing,
+    recording::{Direction, Recorder},
+};
 
 pub struct WebSocketPlugin;
 
@@ -17,7 +26,29 @@ impl Plugin for WebSocketPlugin {
     }
 
     fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
-        vec![Box::new(WebSocket)]
+        vec![
+            Box::new(WebSocket),
+            Box::new(commands::handshake::WebSocketHandshake),
+            Box::new(commands::replay::WebSocketReplay),
+            Box::new(commands::har::WebSocketFromHar),
+            Box::new(commands::har::WebSocketReplayHar),
+            Box::new(commands::pcap::WebSocketFromPcap),
+            Box::new(commands::frame::ToWsFrame),
+            Box::new(commands::frame::FromWsFrames),
+            Box::new(commands::rpc::WebSocketRpc),
+            Box::new(commands::phoenix::WebSocketPhoenix),
+            Box::new(commands::actioncable::WebSocketActionCable),
+            Box::new(commands::cdp::WebSocketCdp),
+            Box::new(commands::nostr::WebSocketNostr),
+            Box::new(commands::pusher::WebSocketPusher),
+            Box::new(commands::ocpp::WebSocketOcppCall),
+            Box::new(commands::ocpp::WebSocketOcppServe),
+            Box::new(commands::xmpp::WebSocketXmpp),
+            Box::new(commands::wamp::WebSocketWampCall),
+            Box::new(commands::wamp::WebSocketWampSubscribe),
+            Box::new(commands::wamp::WebSocketWampPublish),
+            Box::new(commands::signalr::WebSocketSignalR),
+        ]
     }
 }
 
@@ -35,37 +66,52 @@ impl PluginCommand for WebSocket {
     }
 
     fn signature(&self) -> Signature {
-        Signature::build(PluginCommand::name(self))
-            .input_output_types(vec![
-                (Type::Nothing, Type::Any),
-                (Type::String, Type::Any),
-                (Type::Binary, Type::Any),
-            ])
-            .required(
-                "URL",
-                SyntaxShape::String,
-                "The URL to stream from (ws:// or wss://).",
-            )
-            .named(
-                "headers",
-                SyntaxShape::Any,
-                "custom headers you want to add ",
-                Some('H'),
-            )
-            .named(
-                "max-time",
-                SyntaxShape::Duration,
-                "max duration before timeout occurs",
-                Some('m'),
-            )
-            .named(
-                "verbose",
-                SyntaxShape::Int,
-                "verbosity level (0=error, 1=warn, 2=info, 3=debug, 4=trace)",
-                Some('v'),
-            )
-            .filter()
-            .category(Category::Network)
+        connection_flags(
+            Signature::build(PluginCommand::name(self))
+                .input_output_types(vec![
+                    (Type::Nothing, Type::Any),
+                    (Type::String, Type::Any),
+                    (Type::Binary, Type::Any),
+                ])
+                .required(
+                    "URL",
+                    SyntaxShape::String,
+                    "The URL to stream from (ws:// or wss://).",
+                )
+                .named(
+                    "framing",
+                    SyntaxShape::String,
+                    "how messages are delimited in the output: newline (default), none, nul, \
+                     length-prefixed or ndjson-escaped",
+                    None,
+                )
+                .switch(
+                    "timestamps",
+                    "prefix each message with its sequence number and receive time, or add them to \
+                     the --k8s-channels records",
+                    None,
+                )
+                .named(
+                    "latency-field",
+                    SyntaxShape::CellPath,
+                    "prefix each message with its latency, from the send time at this path in its \
+                     JSON payload",
+                    None,
+                )
+                .named(
+                    "record",
+                    SyntaxShape::Filepath,
+                    "write every frame sent and received to this JSON-lines file, for `ws replay`",
+                    None,
+                )
+                .switch(
+                    "k8s-channels",
+                    "speak the Kubernetes exec/attach channel protocol, outputting {stream, data} records",
+                    None,
+                ),
+        )
+        .filter()
+        .category(Category::Network)
     }
 
     fn run(
@@ -75,29 +121,22 @@ impl PluginCommand for WebSocket {
         call: &EvaluatedCall,
         input: PipelineData,
     ) -> Result<PipelineData, LabeledError> {
-        let url: Value = call.req(0)?;
-        let headers: Option<Value> = call.get_flag("headers")?;
-        let timeout: Option<Value> = call.get_flag("max-time")?;
-        let verbose: Option<Value> = call.get_flag("verbose")?;
-
-        // Set up logging based on verbose level
-        let log_level_filter = if let Some(Value::Int { val, .. }) = verbose {
-            match val {
-                0 => log::LevelFilter::Error,
-                1 => log::LevelFilter::Warn,
-                2 => log::LevelFilter::Info,
-                3 => log::LevelFilter::Debug,
-                4 => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
+        if call.has_flag("k8s-channels")? {
+            // The channel protocol outputs records, not the message stream these shape
+            for flag in ["framing", "latency-field", "record"] {
+                if let Some(value) = call.get_flag_value(flag) {
+                    return Err(LabeledError::new(format!(
+                        "--{flag} cannot be used with --k8s-channels"
+                    ))
+                    .with_label("not supported with --k8s-channels", value.span()));
+                }
             }
-        } else {
-            log::LevelFilter::Error // Default to error only
-        };
+            return run_k8s_channels(call, engine, input);
+        }
+
+        let url: Value = call.req(0)?;
 
-        // Initialize env_logger with the specified level (only if not already initialized)
-        let _ = env_logger::Builder::from_default_env()
-            .filter_level(log_level_filter)
-            .try_init();
+        init_logging(call)?;
 
         let span = url.span();
 
@@ -105,28 +144,55 @@ impl PluginCommand for WebSocket {
 
         let (_, requested_url) = http_parse_url(call, span, url)?;
 
-        log::debug!("Connecting to: {requested_url}");
-
-        if ["ws", "wss"].contains(&requested_url.scheme()) {
-            let timeout = timeout.map(|ref val| {
-                let duration = Duration::from_nanos(
-                    val.as_duration()
-                        .expect("Timeout should be set to duration") as u64,
-                );
-                log::trace!("Setting timeout to: {duration:?}");
-                duration
-            });
+        log::debug!("Connecting to: {}", redact_url(&requested_url));
+
+        if ["ws", "wss", "ws+unix"].contains(&requested_url.scheme()) {
+            let timeout = max_time(call)?;
+            let framing = framing(call)?;
+            let timestamps = call.has_flag("timestamps")?;
+            let latency_field: Option<CellPath> = call.get_flag("latency-field")?;
+            // The text prefix would land outside the length prefix or the JSON line
+            if (timestamps || latency_field.is_some())
+                && matches!(framing, Framing::LengthPrefixed | Framing::NdjsonEscaped)
+            {
+                let flag = if timestamps {
+                    "timestamps"
+                } else {
+                    "latency-field"
+                };
+                let span = call
+                    .get_flag_value("framing")
+                    .map_or(call.head, |value| value.span());
+                return Err(LabeledError::new(format!(
+                    "--{flag} cannot be used with this --framing"
+                ))
+                .with_label("the prefix would break this framing", span)
+                .with_help("use newline, nul or none framing to add prefixes"));
+            }
+            let output = OutputOptions {
+                framing,
+                timestamps,
+                latency_field,
+                recorder: recorder(call, engine)?,
+            };
 
             log::trace!("Calling connect function");
 
-            if let Some((client, websocket)) = connect(
-                requested_url,
+            let (requested_url, connector) = connect_options(call, engine, &requested_url, span)?;
+            let jar = cookie_jar(call, engine)?;
+            let headers = handshake_headers(call, &requested_url, jar.as_ref())?;
+
+            if let Some((client, websocket, response)) = connect(
+                requested_url.clone(),
                 timeout,
-                request_headers(headers)?,
+                headers,
+                connector,
+                output.clone(),
                 engine.signals().clone(),
                 span,
             ) {
                 log::debug!("WebSocket connection established successfully");
+                save_cookies(jar, &requested_url, &response);
 
                 // Send input data synchronously before returning ByteStream
                 match input {
@@ -163,6 +229,7 @@ impl PluginCommand for WebSocket {
                             }
                         };
 
+                        output.record(Direction::Sent, &message);
                         ws.send(message).map_err(|e| {
                             LabeledError::new(format!("Failed to send WebSocket message: {e}"))
                         })?;
@@ -185,6 +252,7 @@ impl PluginCommand for WebSocket {
                             Err(_) => tungstenite::Message::Binary(data),
                         };
 
+                        output.record(Direction::Sent, &message);
                         ws.send(message).map_err(|e| {
                             LabeledError::new(format!("Failed to send WebSocket message: {e}"))
                         })?;
@@ -224,3 +292,19 @@ impl PluginCommand for WebSocket {
         Err(LabeledError::new("Unsupported input for command"))
     }
 }
+
+/// Creates the `--record` file, if one was given.
+fn recorder(
+    call: &EvaluatedCall,
+    engine: &EngineInterface,
+) -> Result<Option<Arc<Recorder>>, LabeledError> {
+    let Some(file) = call.get_flag::<Spanned<String>>("record")? else {
+        return Ok(None);
+    };
+    let path = resolve_path(engine, &file)?;
+    let recorder = Recorder::create(&path).map_err(|e| {
+        LabeledError::new(format!("Could not create the recording: {e}"))
+            .with_label("while creating this file", file.span)
+    })?;
+    Ok(Some(Arc::new(recorder)))
+}
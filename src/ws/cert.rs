@@ -0,0 +1,202 @@
+//! Just enough DER decoding to summarize an X.509 certificate.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use sha2::{Digest, Sha256};
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const INTEGER: u8 = 0x02;
+const OID: u8 = 0x06;
+const OCTET_STRING: u8 = 0x04;
+const BOOLEAN: u8 = 0x01;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const BMP_STRING: u8 = 0x1E;
+const VERSION: u8 = 0xA0;
+const EXTENSIONS: u8 = 0xA3;
+const DNS_NAME: u8 = 0x82;
+const IP_ADDRESS: u8 = 0x87;
+
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1D, 0x11];
+
+/// The parts of a certificate worth showing when diagnosing a connection.
+#[derive(Debug, Clone)]
+pub struct CertificateSummary {
+    pub subject: String,
+    pub issuer: String,
+    pub serial: String,
+    pub not_before: Option<DateTime<FixedOffset>>,
+    pub not_after: Option<DateTime<FixedOffset>>,
+    pub subject_alt_names: Vec<String>,
+    pub sha256: String,
+}
+
+/// Summarizes a DER encoded certificate, or returns `None` if it cannot be decoded.
+pub fn summarize(der: &[u8]) -> Option<CertificateSummary> {
+    let mut input = der;
+    let mut certificate = read(&mut input, SEQUENCE)?;
+    let mut tbs = read(&mut certificate, SEQUENCE)?;
+
+    if tbs.first() == Some(&VERSION) {
+        read_any(&mut tbs)?;
+    }
+    let serial = read(&mut tbs, INTEGER)?;
+    read(&mut tbs, SEQUENCE)?; // signature algorithm
+    let issuer = read(&mut tbs, SEQUENCE)?;
+    let mut validity = read(&mut tbs, SEQUENCE)?;
+    let not_before = read_any(&mut validity).and_then(|(tag, value)| time(tag, value));
+    let not_after = read_any(&mut validity).and_then(|(tag, value)| time(tag, value));
+    let subject = read(&mut tbs, SEQUENCE)?;
+    read(&mut tbs, SEQUENCE)?; // subject public key info
+
+    let mut subject_alt_names = vec![];
+    while let Some((tag, value)) = read_any(&mut tbs) {
+        if tag == EXTENSIONS {
+            subject_alt_names = alt_names(value).unwrap_or_default();
+        }
+    }
+
+    Some(CertificateSummary {
+        subject: name(subject)?,
+        issuer: name(issuer)?,
+        serial: hex::encode(serial),
+        not_before,
+        not_after,
+        subject_alt_names,
+        sha256: hex::encode(Sha256::digest(der)),
+    })
+}
+
+/// Reads the next element, whatever its tag.
+fn read_any<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8])> {
+    let tag = *input.first()?;
+    let first = *input.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7F;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let len = input
+            .get(2..2 + count)?
+            .iter()
+            .fold(0, |len, byte| len << 8 | *byte as usize);
+        (len, 2 + count)
+    };
+    let value = input.get(header..header.checked_add(len)?)?;
+    *input = &input[header + len..];
+    Some((tag, value))
+}
+
+/// Reads the next element, which must have `tag`.
+fn read<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    match read_any(input)? {
+        (found, value) if found == tag => Some(value),
+        _ => None,
+    }
+}
+
+/// Formats a distinguished name like `CN=example.com, O=Example`, in certificate order.
+fn name(mut input: &[u8]) -> Option<String> {
+    let mut parts = vec![];
+    while !input.is_empty() {
+        let mut set = read(&mut input, SET)?;
+        while !set.is_empty() {
+            let mut attribute = read(&mut set, SEQUENCE)?;
+            let oid = read(&mut attribute, OID)?;
+            let (tag, value) = read_any(&mut attribute)?;
+            let key = match oid {
+                [0x55, 0x04, 0x03] => "CN".to_string(),
+                [0x55, 0x04, 0x06] => "C".to_string(),
+                [0x55, 0x04, 0x07] => "L".to_string(),
+                [0x55, 0x04, 0x08] => "ST".to_string(),
+                [0x55, 0x04, 0x0A] => "O".to_string(),
+                [0x55, 0x04, 0x0B] => "OU".to_string(),
+                oid => dotted(oid),
+            };
+            parts.push(format!("{key}={}", string(tag, value)));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+fn string(tag: u8, value: &[u8]) -> String {
+    if tag == BMP_STRING {
+        let units: Vec<u16> = value
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        String::from_utf8_lossy(value).into_owned()
+    }
+}
+
+fn dotted(oid: &[u8]) -> String {
+    let Some((first, rest)) = oid.split_first() else {
+        return String::new();
+    };
+    let mut parts = vec![(first / 40).to_string(), (first % 40).to_string()];
+    let mut value: u64 = 0;
+    for byte in rest {
+        value = value << 7 | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            parts.push(value.to_string());
+            value = 0;
+        }
+    }
+    parts.join(".")
+}
+
+fn time(tag: u8, value: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let value = std::str::from_utf8(value).ok()?;
+    let value = match tag {
+        UTC_TIME => {
+            let year: u32 = value.get(..2)?.parse().ok()?;
+            let century = if year >= 50 { "19" } else { "20" };
+            format!("{century}{value}")
+        }
+        GENERALIZED_TIME => value.to_string(),
+        _ => return None,
+    };
+    let time = NaiveDateTime::parse_from_str(&value, "%Y%m%d%H%M%SZ").ok()?;
+    Some(time.and_utc().fixed_offset())
+}
+
+/// Reads the DNS names and IP addresses of the subject alternative name extension.
+fn alt_names(extensions: &[u8]) -> Option<Vec<String>> {
+    let mut extensions = extensions;
+    let mut extensions = read(&mut extensions, SEQUENCE)?;
+    while !extensions.is_empty() {
+        let mut extension = read(&mut extensions, SEQUENCE)?;
+        if read(&mut extension, OID)? != SUBJECT_ALT_NAME {
+            continue;
+        }
+        if extension.first() == Some(&BOOLEAN) {
+            read_any(&mut extension)?;
+        }
+        let mut value = read(&mut extension, OCTET_STRING)?;
+        let mut general_names = read(&mut value, SEQUENCE)?;
+
+        let mut names = vec![];
+        while let Some((tag, value)) = read_any(&mut general_names) {
+            match tag {
+                DNS_NAME => names.push(String::from_utf8_lossy(value).into_owned()),
+                IP_ADDRESS => {
+                    let ip = match value.len() {
+                        4 => IpAddr::from(<[u8; 4]>::try_from(value).ok()?),
+                        16 => IpAddr::from(<[u8; 16]>::try_from(value).ok()?),
+                        _ => continue,
+                    };
+                    names.push(ip.to_string());
+                }
+                _ => {}
+            }
+        }
+        return Some(names);
+    }
+    Some(vec![])
+}
@@ -1,9 +1,17 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use nu_plugin::EvaluatedCall;
-use nu_protocol::{ShellError, Signals, Span, Value};
+use nu_protocol::{ast::CellPath, Record, ShellError, Signals, Span, Value};
+use percent_encoding::percent_decode_str;
 use url::Url;
 
-#[cfg(not(target_arch = "wasm32"))]
-use std::collections::HashMap;
+use super::{
+    connector::{Connector, Transport},
+    framing::Framing,
+    headers::Headers,
+    receipt::{Receipt, Receipts},
+    recording::{Direction, Recorder},
+};
+
 use std::{
     collections::VecDeque,
     io::Read,
@@ -14,10 +22,18 @@ use std::{
     thread,
     time::{Duration, Instant},
 };
-use tungstenite::ClientRequestBuilder;
+use tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::{Request, Response},
+    http::{HeaderName, HeaderValue, Uri},
+};
+
+pub type WebSocketStream = tungstenite::WebSocket<Transport>;
 
-type WebSocketConnection =
-    Arc<Mutex<tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>>>;
+type WebSocketConnection = Arc<Mutex<WebSocketStream>>;
+
+/// How long a blocking read waits before giving other users of the socket a turn.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct WebSocketClient {
     rx: Arc<Mutex<Receiver<Vec<u8>>>>,
@@ -65,7 +81,6 @@ impl Read for WebSocketClient {
         }
 
         let rx = self.rx.lock().expect("Could not get lock on receiver");
-        let poll_interval = Duration::from_millis(100);
 
         // Poll for new data with regular signal checking
         loop {
@@ -83,7 +98,7 @@ impl Read for WebSocketClient {
                     match deadline.checked_duration_since(Instant::now()) {
                         Some(remaining) => {
                             // Use the smaller of remaining time or poll interval
-                            remaining.min(poll_interval)
+                            remaining.min(POLL_INTERVAL)
                         }
                         None => {
                             // Deadline has already passed
@@ -91,7 +106,7 @@ impl Read for WebSocketClient {
                         }
                     }
                 }
-                None => poll_interval, // No deadline, just use poll interval
+                None => POLL_INTERVAL, // No deadline, just use poll interval
             };
 
             // Poll for data with timeout
@@ -127,38 +142,246 @@ impl Read for WebSocketClient {
     }
 }
 
-pub fn connect(
-    url: Url,
-    timeout: Option<Duration>,
-    headers: HashMap<String, String>,
-    signals: Signals,
-    span: Span,
-) -> Option<(WebSocketClient, WebSocketConnection)> {
-    log::trace!("Building WebSocket request for: {url}");
+/// Performs the WebSocket handshake for `url` through `connector`.
+#[allow(clippy::result_large_err)]
+pub fn handshake(
+    url: &Url,
+    headers: Headers,
+    subprotocols: &[String],
+    mut connector: Connector,
+) -> tungstenite::Result<(WebSocketStream, Response)> {
+    let request = build_request(url, headers, subprotocols)?;
 
-    let mut builder = ClientRequestBuilder::new(url.as_str().parse().ok()?);
-    let origin = format!(
-        "{}://{}:{}",
-        url.scheme(),
-        url.host_str().unwrap_or_default(),
-        url.port().unwrap_or_default()
-    );
+    log::debug!("Attempting WebSocket connection...");
 
-    log::trace!("Setting Origin header to: {origin}");
+    connector.connect(request)
+}
+
+/// Builds the upgrade request for `url`.
+///
+/// `headers` are sent in order and replace any header of the same name the request would
+/// otherwise carry, including the ones tungstenite generates such as `Host`.
+#[allow(clippy::result_large_err)]
+pub fn build_request(
+    url: &Url,
+    headers: Headers,
+    subprotocols: &[String],
+) -> tungstenite::Result<Request> {
+    let mut headers = headers;
+    let url = &take_credentials(url, &mut headers);
 
-    builder = builder.with_header("Origin", origin);
+    log::trace!("Building WebSocket request for: {}", redact_url(url));
 
+    let mut request = url.as_str().into_client_request()?;
+
+    let mut replaced = vec![];
     for (k, v) in headers {
-        log::trace!("Adding header: {k} = {v}");
-        builder = builder.with_header(k, v);
+        log::trace!("Setting header: {k} = {}", redact_header(&k, &v));
+        let name = HeaderName::try_from(k)?;
+        let value = HeaderValue::try_from(v)?;
+        // The first header of a name replaces the default, the ones after it are added
+        if replaced.contains(&name) {
+            request.headers_mut().append(name, value);
+        } else {
+            replaced.push(name.clone());
+            request.headers_mut().insert(name, value);
+        }
     }
 
-    log::debug!("Attempting WebSocket connection...");
+    // tungstenite joins subprotocols with ", " but its response check splits on "," without
+    // trimming, so any protocol but the first would be rejected when the server selects it
+    if !subprotocols.is_empty() && !request.headers().contains_key("Sec-WebSocket-Protocol") {
+        log::trace!("Requesting subprotocols: {subprotocols:?}");
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::try_from(subprotocols.join(","))?,
+        );
+    }
+
+    Ok(request)
+}
+
+/// The `Origin` a browser would send when opening `url`: the http(s) origin, with the
+/// scheme's default port left out.
+pub fn default_origin(url: &Url) -> String {
+    let mut url = url.clone();
+    let scheme = match url.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        scheme => scheme,
+    }
+    .to_string();
+    let _ = url.set_scheme(&scheme);
+    url.origin().ascii_serialization()
+}
+
+/// Headers whose values are never written to logs.
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+/// Query parameters whose values are never written to logs.
+const SENSITIVE_PARAMS: &[&str] = &[
+    "token",
+    "access_token",
+    "api_key",
+    "apikey",
+    "key",
+    "secret",
+    "password",
+    "auth",
+    "signature",
+];
+
+/// Formats `url` for logs, leaving out any credentials embedded in it.
+pub fn redact_url(url: &Url) -> String {
+    let mut url = without_credentials(url);
+    let sensitive = |name: &str| {
+        SENSITIVE_PARAMS
+            .iter()
+            .any(|param| name.eq_ignore_ascii_case(param))
+    };
+    if url.query_pairs().any(|(name, _)| sensitive(&name)) {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if sensitive(&name) {
+                    "***".into()
+                } else {
+                    value
+                };
+                (name.into_owned(), value.into_owned())
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// Formats `uri` for logs like [`redact_url`].
+pub fn redact_uri(uri: &Uri) -> String {
+    match Url::parse(&uri.to_string()) {
+        Ok(url) => redact_url(&url),
+        Err(_) => uri.path().to_string(),
+    }
+}
+
+fn without_credentials(url: &Url) -> Url {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url
+}
+
+/// Returns `value`, or a placeholder when the header `name` carries credentials.
+pub fn redact_header<'a>(name: &str, value: &'a str) -> &'a str {
+    if SENSITIVE_HEADERS
+        .iter()
+        .any(|sensitive| name.eq_ignore_ascii_case(sensitive))
+    {
+        "<redacted>"
+    } else {
+        value
+    }
+}
+
+/// Builds a `Basic` `Authorization` header value.
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!("Basic {}", BASE64.encode(format!("{user}:{password}")))
+}
+
+/// Returns `url` without its userinfo, moving the credentials into a `Basic` `Authorization`
+/// header unless `headers` already has one.
+pub fn take_credentials(url: &Url, headers: &mut Headers) -> Url {
+    if url.username().is_empty() && url.password().is_none() {
+        return url.clone();
+    }
+
+    if !headers.contains("Authorization") {
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let user = decode(url.username());
+        let password = decode(url.password().unwrap_or_default());
+        log::trace!("Using credentials from the URL for user: {user}");
+        headers.append("Authorization", basic_auth(&user, &password));
+    }
+
+    without_credentials(url)
+}
+
+/// Sets the read timeout of the TCP stream underneath `websocket`.
+pub fn set_read_timeout(
+    websocket: &WebSocketStream,
+    timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    websocket.get_ref().set_read_timeout(timeout)
+}
+
+/// Whether `error` only means that a read timed out before a full message arrived.
+pub fn is_read_timeout(error: &tungstenite::Error) -> bool {
+    matches!(
+        error,
+        tungstenite::Error::Io(e)
+            if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+    )
+}
+
+/// How the reader thread of [`connect`] writes received messages to the byte stream.
+#[derive(Clone, Default)]
+pub struct OutputOptions {
+    pub framing: Framing,
+    /// Prefix each message with its sequence number and receive time.
+    pub timestamps: bool,
+    /// Also prefix each message with its latency, from the timestamp at this path in its
+    /// JSON payload.
+    pub latency_field: Option<CellPath>,
+    /// Write every frame to this recording, including the ones sent by the caller.
+    pub recorder: Option<Arc<Recorder>>,
+}
+
+impl OutputOptions {
+    /// Adds `message` to the recording, if there is one.
+    pub fn record(&self, direction: Direction, message: &tungstenite::Message) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(direction, message);
+        }
+    }
+
+    fn encode(&self, message: tungstenite::Message, receipt: Receipt) -> Vec<u8> {
+        let payload = match &message {
+            tungstenite::Message::Text(text) => text.as_bytes(),
+            tungstenite::Message::Binary(data) => data,
+            _ => &[],
+        };
+        let latency = self
+            .latency_field
+            .as_ref()
+            .map(|path| receipt.latency(payload, path));
+        let data = self.framing.encode(message).unwrap_or_default();
+        if !self.timestamps && latency.is_none() {
+            return data;
+        }
+        let mut prefixed = receipt.prefix(latency).into_bytes();
+        prefixed.extend(data);
+        prefixed
+    }
+}
 
-    match tungstenite::connect(builder) {
-        Ok((websocket, _)) => {
+pub fn connect(
+    url: Url,
+    timeout: Option<Duration>,
+    headers: Headers,
+    connector: Connector,
+    output: OutputOptions,
+    signals: Signals,
+    span: Span,
+) -> Option<(WebSocketClient, WebSocketConnection, Response)> {
+    match handshake(&url, headers, &[], connector) {
+        Ok((websocket, response)) => {
             log::debug!("WebSocket handshake completed successfully");
 
+            // Reads give up periodically so that writers can take the lock in between
+            if let Err(e) = set_read_timeout(&websocket, Some(POLL_INTERVAL)) {
+                log::warn!("Could not set read timeout: {e}");
+            }
+
             let (tx_read, rx_read) = mpsc::sync_channel(1024);
 
             log::trace!("Created channel for reader communication");
@@ -172,51 +395,49 @@ pub fn connect(
                 .name("websocket reader".to_string())
                 .spawn(move || {
                     log::debug!("WebSocket reader thread started");
+                    let mut receipts = Receipts::new();
                     loop {
                         let tx_read = tx_read.clone();
                         let mut ws = ws_clone.lock().unwrap();
                         match ws.read() {
-                            Ok(msg) => match msg {
-                                tungstenite::Message::Text(msg) => {
-                                    log::debug!("Received Text message: {} bytes", msg.len());
-                                    log::trace!("Text content: {msg:?}");
-                                    // Add newline after each WebSocket message for proper line separation
-                                    let mut data = msg.into_bytes();
-                                    data.push(b'\n');
-                                    if tx_read.send(data).is_err() {
-                                        log::debug!("Channel closed, closing WebSocket");
-                                        ws.close(Some(tungstenite::protocol::CloseFrame{
-                                            code: tungstenite::protocol::frame::coding::CloseCode::Normal,
-                                            reason: std::borrow::Cow::Borrowed("byte stream closed"),
-                                        })).expect("Could not close connection");
-                                        return;
+                            Ok(msg) => {
+                                // Stamped before recording, so the file write does not count
+                                let receipt = (msg.is_text() || msg.is_binary())
+                                    .then(|| receipts.stamp());
+                                output.record(Direction::Received, &msg);
+                                match (msg, receipt) {
+                                    (msg, Some(receipt)) => {
+                                        log::debug!("Received message {}: {} bytes", receipt.seq, msg.len());
+                                        log::trace!("Message content: {msg:?}");
+                                        let data = output.encode(msg, receipt);
+                                        if tx_read.send(data).is_err() {
+                                            log::debug!("Channel closed, closing WebSocket");
+                                            let close = tungstenite::protocol::CloseFrame{
+                                                code: tungstenite::protocol::frame::coding::CloseCode::Normal,
+                                                reason: std::borrow::Cow::Borrowed("byte stream closed"),
+                                            };
+                                            output.record(Direction::Sent, &tungstenite::Message::Close(Some(close.clone())));
+                                            ws.close(Some(close)).expect("Could not close connection");
+                                            return;
+                                        }
+                                        log::trace!("Message sent to channel successfully, continuing to read...");
                                     }
-                                    log::trace!("Message sent to channel successfully, continuing to read...");
-                                }
-                                tungstenite::Message::Binary(msg) => {
-                                    log::debug!("Received Binary message: {} bytes", msg.len());
-                                    // Add newline after each WebSocket message for proper line separation
-                                    let mut data = msg;
-                                    data.push(b'\n');
-                                    if tx_read.send(data).is_err() {
-                                        log::debug!("Channel closed, closing WebSocket");
-                                        ws.close(Some(tungstenite::protocol::CloseFrame{
-                                            code: tungstenite::protocol::frame::coding::CloseCode::Normal,
-                                            reason: std::borrow::Cow::Borrowed("byte stream closed"),
-                                        })).expect("Could not close connection");
+                                    (tungstenite::Message::Close(..), None) => {
+                                        log::debug!("Received Close message");
+                                        drop(tx_read);
                                         return;
                                     }
+                                    (msg, None) => {
+                                        log::trace!("Received other message type: {msg:?}");
+                                        continue;
+                                    }
                                 }
-                                tungstenite::Message::Close(..) => {
-                                    log::debug!("Received Close message");
-                                    drop(tx_read);
-                                    return;
-                                }
-                                _ => {
-                                    log::trace!("Received other message type: {msg:?}");
-                                    continue;
-                                }
-                            },
+                            }
+                            Err(e) if is_read_timeout(&e) => {
+                                drop(ws);
+                                thread::sleep(Duration::from_millis(1));
+                                continue;
+                            }
                             Err(e) => {
                                 log::error!("WebSocket read error: {e:?}");
                                 log::debug!("WebSocket reader thread exiting due to error");
@@ -233,6 +454,7 @@ pub fn connect(
             Some((
                 WebSocketClient::new(rx_read, timeout, signals, span),
                 websocket,
+                response,
             ))
         }
         Err(e) => {
@@ -257,67 +479,164 @@ pub fn http_parse_url(
         }
     };
 
+    let mut url = url;
+    append_query_flag(call, &mut url)?;
+
     Ok((requested_url, url))
 }
 
+/// Appends the `--query` flag, if given, to the query string of `url`.
+#[allow(clippy::result_large_err)]
+pub fn append_query_flag(call: &EvaluatedCall, url: &mut Url) -> Result<(), ShellError> {
+    if let Some(Value::Record { val, .. }) = call.get_flag("query")? {
+        append_query(url, &val)?;
+    }
+    Ok(())
+}
+
+/// Appends the `--query` record to the query string of `url`, repeating the key of a list.
 #[allow(clippy::result_large_err)]
-pub fn request_headers(headers: Option<Value>) -> Result<HashMap<String, String>, ShellError> {
-    let mut custom_headers: HashMap<String, Value> = HashMap::new();
+fn append_query(url: &mut Url, query: &Record) -> Result<(), ShellError> {
+    if query.is_empty() {
+        return Ok(());
+    }
 
-    if let Some(headers) = headers {
-        match &headers {
-            Value::Record { val, .. } => {
+    let mut pairs = url.query_pairs_mut();
+    for (key, value) in query {
+        let values = match value {
+            Value::List { vals, .. } => vals.as_slice(),
+            value => std::slice::from_ref(value),
+        };
+        for value in values {
+            let text = value
+                .coerce_string()
+                .map_err(|_| ShellError::GenericError {
+                    error: "Invalid query value".into(),
+                    msg: format!(
+                        "expected a string, number or boolean for {key}, found {}",
+                        value.get_type()
+                    ),
+                    span: Some(value.span()),
+                    help: None,
+                    inner: vec![],
+                })?;
+            pairs.append_pair(key, &text);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the `--headers` flag: a record, a single-row table or a `[name value ...]` list.
+///
+/// Headers keep the order they were given in. A list value sends the header once per item, so
+/// `{Accept: [a b]}` sends two `Accept` headers.
+#[allow(clippy::result_large_err)]
+pub fn request_headers(headers: Option<Value>) -> Result<Headers, ShellError> {
+    let mut result = Headers::new();
+    let Some(headers) = headers else {
+        return Ok(result);
+    };
+
+    match &headers {
+        Value::Record { val, internal_span } => {
+            for (k, v) in &**val {
+                push_header(&mut result, k, *internal_span, v)?;
+            }
+        }
+
+        Value::List { vals: table, .. } => match table.as_slice() {
+            // single row([key1 key2]; [val1 val2])
+            [Value::Record { val, internal_span }] => {
                 for (k, v) in &**val {
-                    custom_headers.insert(k.to_string(), v.clone());
+                    push_header(&mut result, k, *internal_span, v)?;
                 }
             }
 
-            Value::List { vals: table, .. } => {
-                if table.len() == 1 {
-                    // single row([key1 key2]; [val1 val2])
-                    match &table[0] {
-                        Value::Record { val, .. } => {
-                            for (k, v) in &**val {
-                                custom_headers.insert(k.to_string(), v.clone());
-                            }
-                        }
-
-                        x => {
-                            return Err(ShellError::CantConvert {
-                                to_type: "string list or single row".into(),
-                                from_type: x.get_type().to_string(),
-                                span: headers.span(),
-                                help: None,
-                            });
-                        }
-                    }
-                } else {
-                    // primitive values ([key1 val1 key2 val2])
-                    for row in table.chunks(2) {
-                        if row.len() == 2 {
-                            custom_headers.insert(row[0].coerce_string()?, row[1].clone());
-                        }
-                    }
+            // primitive values ([key1 val1 key2 val2])
+            _ => {
+                if let Some(row) = table.iter().find(|v| matches!(v, Value::Record { .. })) {
+                    return Err(ShellError::CantConvert {
+                        to_type: "string list or single row".into(),
+                        from_type: row.get_type().to_string(),
+                        span: row.span(),
+                        help: None,
+                    });
+                }
+                if table.len() % 2 != 0 {
+                    return Err(ShellError::GenericError {
+                        error: "Header list has a name without a value".into(),
+                        msg: "this header has no value".into(),
+                        span: table.last().map(Value::span),
+                        help: Some("use a list of alternating names and values".into()),
+                        inner: vec![],
+                    });
+                }
+                for row in table.chunks_exact(2) {
+                    let name = row[0].coerce_string()?;
+                    push_header(&mut result, &name, row[0].span(), &row[1])?;
                 }
             }
+        },
+
+        x => {
+            return Err(ShellError::CantConvert {
+                to_type: "string list or single row".into(),
+                from_type: x.get_type().to_string(),
+                span: headers.span(),
+                help: None,
+            });
+        }
+    };
 
-            x => {
-                return Err(ShellError::CantConvert {
-                    to_type: "string list or single row".into(),
-                    from_type: x.get_type().to_string(),
-                    span: headers.span(),
-                    help: None,
-                });
-            }
-        };
+    Ok(result)
+}
+
+#[allow(clippy::result_large_err)]
+fn push_header(
+    headers: &mut Headers,
+    name: &str,
+    name_span: Span,
+    value: &Value,
+) -> Result<(), ShellError> {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(ShellError::GenericError {
+            error: "Invalid header name".into(),
+            msg: format!("{name:?} is not a valid header name"),
+            span: Some(name_span),
+            help: None,
+            inner: vec![],
+        });
     }
 
-    let mut result = HashMap::new();
-    for (k, v) in custom_headers {
-        if let Ok(s) = v.coerce_into_string() {
-            result.insert(k, s);
+    let values = match value {
+        Value::List { vals, .. } => vals.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    for value in values {
+        let text = value
+            .coerce_string()
+            .map_err(|_| ShellError::GenericError {
+                error: "Invalid header value".into(),
+                msg: format!(
+                    "expected a string, number or boolean for {name}, found {}",
+                    value.get_type()
+                ),
+                span: Some(value.span()),
+                help: None,
+                inner: vec![],
+            })?;
+        if HeaderValue::from_str(&text).is_err() {
+            return Err(ShellError::GenericError {
+                error: "Invalid header value".into(),
+                msg: "contains characters not allowed in a header, such as a line break".into(),
+                span: Some(value.span()),
+                help: None,
+                inner: vec![],
+            });
         }
+        headers.append(name, text);
     }
 
-    Ok(result)
+    Ok(())
 }
@@ -0,0 +1,529 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use percent_encoding::percent_decode_str;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use tungstenite::{
+    error::{TlsError, UrlError},
+    handshake::{
+        client::{Request, Response},
+        HandshakeError,
+    },
+    http::Uri,
+    Error,
+};
+use url::Url;
+
+use super::client::{redact_uri, WebSocketStream};
+
+/// The stream a client connection runs over.
+#[derive(Debug)]
+pub enum Transport {
+    Plain(TcpStream),
+    Tls(native_tls::TlsStream<TcpStream>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Transport {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.set_read_timeout(timeout),
+            Self::Tls(stream) => stream.get_ref().set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// How long a connection attempt gets before the next address is tried alongside it, as
+/// recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How long a single connection attempt may take, so that an address that drops packets
+/// does not wait out the system's TCP timeout, which can take minutes.
+const CONNECTION_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An IP address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Self::V4,
+            IpAddr::V6(_) => Self::V6,
+        }
+    }
+}
+
+impl fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4 => write!(f, "IPv4"),
+            Self::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// Fixed addresses for a host and port, like curl's `--resolve host:port:addr[,addr]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub port: u16,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected host:port:address".into());
+        };
+        if host.is_empty() {
+            return Err("the host is missing".into());
+        }
+        let port = port
+            .parse()
+            .map_err(|_| format!("{port:?} is not a valid port"))?;
+        let addrs = addrs
+            .split(',')
+            .map(|addr| {
+                let addr = addr.trim().trim_start_matches('[').trim_end_matches(']');
+                addr.parse()
+                    .map_err(|_| format!("{addr:?} is not an IP address"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            addrs,
+        })
+    }
+}
+
+/// Orders addresses so that the families alternate, starting with the first one resolved.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first().map(|addr| IpFamily::of(addr.ip())) else {
+        return addrs;
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| IpFamily::of(addr.ip()) == first);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut ordered = vec![];
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connects to the first of `addrs` that answers, starting another attempt whenever the
+/// previous one fails or has not succeeded after [`CONNECTION_ATTEMPT_DELAY`] (Happy Eyeballs).
+///
+/// Each attempt gives up after [`CONNECTION_ATTEMPT_TIMEOUT`] or at `deadline`, whichever
+/// comes first.
+fn dial(
+    addrs: &[SocketAddr],
+    local_addr: Option<IpAddr>,
+    deadline: Option<Instant>,
+) -> io::Result<TcpStream> {
+    if let [addr] = addrs {
+        return connect_from(*addr, local_addr, attempt_timeout(deadline)?);
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut pending = addrs.iter().copied();
+    let mut running = 0;
+    let mut last_error = None;
+
+    loop {
+        if let Some(addr) = pending.next() {
+            match attempt_timeout(deadline) {
+                Ok(timeout) => {
+                    let tx = tx.clone();
+                    thread::spawn(move || {
+                        // The receiver is gone once another attempt has won
+                        let _ = tx.send(connect_from(addr, local_addr, timeout));
+                    });
+                    running += 1;
+                }
+                Err(e) => {
+                    // Too late for the rest too
+                    pending.by_ref().for_each(drop);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if running == 0 {
+            return Err(last_error.unwrap_or_else(|| io::ErrorKind::NotFound.into()));
+        }
+
+        let result = if pending.len() > 0 {
+            match rx.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+                Ok(result) => result,
+                Err(_) => continue,
+            }
+        } else {
+            rx.recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?
+        };
+        running -= 1;
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+}
+
+/// How long the next connection attempt may take.
+fn attempt_timeout(deadline: Option<Instant>) -> io::Result<Duration> {
+    let Some(deadline) = deadline else {
+        return Ok(CONNECTION_ATTEMPT_TIMEOUT);
+    };
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(remaining) if !remaining.is_zero() => Ok(remaining.min(CONNECTION_ATTEMPT_TIMEOUT)),
+        _ => Err(timed_out()),
+    }
+}
+
+fn connect_from(
+    addr: SocketAddr,
+    local_addr: Option<IpAddr>,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    log::debug!("Trying to connect to {addr}...");
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let Some(local_addr) = local_addr {
+        socket.bind(&SocketAddr::new(local_addr, 0).into())?;
+    }
+    socket
+        .connect_timeout(&addr.into(), timeout)
+        .inspect_err(|e| {
+            log::debug!("Could not connect to {addr}: {e}");
+        })?;
+    Ok(socket.into())
+}
+
+/// Splits a `ws+unix:///path/to.sock:/ws/path` URL into the socket path and the `ws://` URL
+/// to request over it. The request path defaults to `/`, and the socket path is
+/// percent-decoded, since the URL parser encodes characters like spaces.
+pub fn unix_socket_url(url: &Url) -> Option<(PathBuf, Url)> {
+    if url.scheme() != "ws+unix" {
+        return None;
+    }
+    let (socket, path) = url.path().split_once(':').unwrap_or((url.path(), "/"));
+    let mut request_url = Url::parse("ws://localhost/").ok()?;
+    request_url.set_path(path);
+    request_url.set_query(url.query());
+    let socket = percent_decode_str(socket).decode_utf8_lossy();
+    Some((PathBuf::from(socket.as_ref()), request_url))
+}
+
+/// How many redirects a handshake follows by default, the same as `tungstenite::connect`.
+pub const MAX_REDIRECTS: u8 = 3;
+
+/// How long each step of the last connection attempt took.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    pub dns: Option<Duration>,
+    pub tcp: Option<Duration>,
+    pub tls: Option<Duration>,
+    pub upgrade: Option<Duration>,
+}
+
+/// Opens WebSocket connections one step at a time, keeping track of what each step found.
+///
+/// The fields describe the last attempt and are filled in even when it fails, which is what
+/// lets `ws handshake` report how far a broken endpoint got.
+#[derive(Debug)]
+pub struct Connector {
+    pub max_redirects: u8,
+    /// Connect to this Unix socket instead of the host in the URL.
+    pub unix_socket: Option<PathBuf>,
+    /// Addresses to use instead of resolving some hosts.
+    pub resolve: Vec<ResolveOverride>,
+    /// Only connect over this address family.
+    pub family: Option<IpFamily>,
+    /// Bind outgoing connections to this address.
+    pub local_addr: Option<IpAddr>,
+    /// Give up on connecting and upgrading at this time, from `--max-time`.
+    pub deadline: Option<Instant>,
+    pub timings: Timings,
+    pub peer_addr: Option<SocketAddr>,
+    /// The DER encoded certificate the server presented, for wss:// URLs.
+    pub peer_certificate: Option<Vec<u8>>,
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self {
+            max_redirects: MAX_REDIRECTS,
+            unix_socket: None,
+            resolve: vec![],
+            family: None,
+            local_addr: None,
+            deadline: None,
+            timings: Timings::default(),
+            peer_addr: None,
+            peer_certificate: None,
+        }
+    }
+}
+
+impl Connector {
+    /// Connects and performs the upgrade, following redirects like `tungstenite::connect`.
+    #[allow(clippy::result_large_err)]
+    pub fn connect(
+        &mut self,
+        request: Request,
+    ) -> tungstenite::Result<(WebSocketStream, Response)> {
+        let (parts, _) = request.into_parts();
+        let mut uri = parts.uri.clone();
+
+        for attempt in 0..=self.max_redirects {
+            let mut request = Request::from_parts(parts.clone(), ());
+            *request.uri_mut() = uri.clone();
+
+            match self.try_connect(request) {
+                Err(Error::Http(response))
+                    if response.status().is_redirection() && attempt < self.max_redirects =>
+                {
+                    let Some(location) = response.headers().get("Location") else {
+                        log::warn!("No `Location` found in redirect");
+                        return Err(Error::Http(response));
+                    };
+                    uri = location.to_str()?.parse::<Uri>()?;
+                    log::debug!("Redirecting to {}", redact_uri(&uri));
+                }
+                other => return other,
+            }
+        }
+
+        unreachable!("the last attempt always returns")
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn try_connect(
+        &mut self,
+        request: Request,
+    ) -> tungstenite::Result<(WebSocketStream, Response)> {
+        let uri = request.uri();
+        let tls = match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") => true,
+            _ => return Err(Error::Url(UrlError::UnsupportedUrlScheme)),
+        };
+        let host = uri.host().ok_or(Error::Url(UrlError::NoHostName))?;
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+
+        let stream = self.open(&host, port, tls).map_err(|e| match e {
+            Error::Io(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                log::debug!("Could not connect: {e}");
+                Error::Url(UrlError::UnableToConnect(redact_uri(uri)))
+            }
+            e => e,
+        })?;
+        self.upgrade(request, stream)
+    }
+
+    /// Opens the stream to `host:port`, or to the Unix socket, wrapped in TLS when `tls` is
+    /// set. This is the part of a connection shared with plain HTTP requests.
+    #[allow(clippy::result_large_err)]
+    pub fn open(&mut self, host: &str, port: u16, tls: bool) -> tungstenite::Result<Transport> {
+        self.timings = Timings::default();
+        self.peer_addr = None;
+        self.peer_certificate = None;
+
+        if let Some(path) = &self.unix_socket {
+            if tls {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "TLS over Unix sockets is not supported",
+                )));
+            }
+            let stream = unix_stream(path, &mut self.timings)?;
+            return Ok(stream);
+        }
+
+        let start = Instant::now();
+        let addrs = self.resolve(host, port)?;
+        self.timings.dns = Some(start.elapsed());
+        log::debug!("Resolved {host} to {addrs:?}");
+
+        let start = Instant::now();
+        let stream = dial(&addrs, self.local_addr, self.deadline)?;
+        stream.set_nodelay(true)?;
+        self.timings.tcp = Some(start.elapsed());
+        self.peer_addr = stream.peer_addr().ok();
+
+        if !tls {
+            return Ok(Transport::Plain(stream));
+        }
+
+        let start = Instant::now();
+        let connector = native_tls::TlsConnector::new().map_err(TlsError::Native)?;
+        stream.set_read_timeout(self.remaining()?)?;
+        // SNI and certificate checks use the host from the URL, even with --resolve
+        let stream = connector.connect(host, stream).map_err(|e| match e {
+            native_tls::HandshakeError::Failure(e) => Error::Tls(e.into()),
+            // The stream is blocking, so this only happens when the read timeout expires
+            native_tls::HandshakeError::WouldBlock(_) => Error::Io(timed_out()),
+        })?;
+        stream.get_ref().set_read_timeout(None)?;
+        self.timings.tls = Some(start.elapsed());
+        self.peer_certificate = stream
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|certificate| certificate.to_der().ok());
+        Ok(Transport::Tls(stream))
+    }
+
+    /// The addresses to try for `host:port`, in the order Happy Eyeballs tries them.
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let overridden = self
+            .resolve
+            .iter()
+            .find(|o| o.port == port && o.host.eq_ignore_ascii_case(host));
+        let addrs: Vec<SocketAddr> = match overridden {
+            Some(o) => {
+                log::debug!("Using the --resolve addresses for {host}:{port}");
+                o.addrs
+                    .iter()
+                    .map(|ip| SocketAddr::new(*ip, port))
+                    .collect()
+            }
+            None => (host, port).to_socket_addrs()?.collect(),
+        };
+
+        // A local address can only reach addresses of its own family
+        let family = self.family.or(self.local_addr.map(IpFamily::of));
+        let addrs: Vec<SocketAddr> = addrs
+            .into_iter()
+            .filter(|addr| family.is_none_or(|family| family == IpFamily::of(addr.ip())))
+            .collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                match family {
+                    Some(family) => format!("{host} has no {family} address"),
+                    None => format!("{host} has no address"),
+                },
+            ));
+        }
+
+        Ok(interleave(addrs))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn upgrade(
+        &mut self,
+        request: Request,
+        stream: Transport,
+    ) -> tungstenite::Result<(WebSocketStream, Response)> {
+        let start = Instant::now();
+        stream.set_read_timeout(self.remaining()?)?;
+        let result = tungstenite::client(request, stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => e,
+            // The stream is blocking, so this only happens when the read timeout expires
+            HandshakeError::Interrupted(_) => Error::Io(timed_out()),
+        });
+        self.timings.upgrade = Some(start.elapsed());
+        let (websocket, response) = result?;
+        websocket.get_ref().set_read_timeout(None)?;
+        Ok((websocket, response))
+    }
+
+    /// The time left until the deadline, or an error once it has passed.
+    fn remaining(&self) -> io::Result<Option<Duration>> {
+        let Some(deadline) = self.deadline else {
+            return Ok(None);
+        };
+        match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(Some(remaining)),
+            _ => Err(timed_out()),
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        "--max-time reached while connecting",
+    )
+}
+
+#[cfg(unix)]
+#[allow(clippy::result_large_err)]
+fn unix_stream(path: &Path, timings: &mut Timings) -> tungstenite::Result<Transport> {
+    log::debug!("Connecting to Unix socket {}", path.display());
+    let start = Instant::now();
+    let stream = UnixStream::connect(path)?;
+    timings.tcp = Some(start.elapsed());
+    Ok(Transport::Unix(stream))
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::result_large_err)]
+fn unix_stream(_path: &Path, _timings: &mut Timings) -> tungstenite::Result<Transport> {
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    )))
+}
@@ -0,0 +1,321 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, Utc};
+use url::Url;
+
+const HEADER: &str = "# Netscape HTTP Cookie File\n\
+                      # This file was generated by nu_plugin_ws. Edit at your own risk.\n\n";
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// A cookie as stored in a Netscape (curl) cookie file.
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    /// The domain without a leading dot.
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    /// Unix time the cookie expires at, or 0 for a session cookie.
+    pub expires: i64,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    fn parse_line(line: &str) -> Option<Self> {
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(line) => (line, true),
+            None if line.starts_with('#') || line.trim().is_empty() => return None,
+            None => (line, false),
+        };
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return None;
+        };
+        Some(Self {
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            http_only,
+            expires: expires.parse().ok()?,
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let bool_field = |b: bool| if b { "TRUE" } else { "FALSE" };
+        format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            if self.http_only { HTTP_ONLY_PREFIX } else { "" },
+            if self.include_subdomains { "." } else { "" },
+            self.domain,
+            bool_field(self.include_subdomains),
+            self.path,
+            bool_field(self.secure),
+            self.expires,
+            self.name,
+            self.value
+        )
+    }
+
+    /// Parses a `Set-Cookie` header received in response to a request for `url`.
+    ///
+    /// Returns `None` for malformed cookies and for cookies `url` may not set.
+    pub fn parse_set_cookie(url: &Url, header: &str, now: i64) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Self {
+            domain: host.clone(),
+            include_subdomains: false,
+            path: default_path(url),
+            secure: false,
+            http_only: false,
+            expires: 0,
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+        };
+        let mut max_age = None;
+
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    // A single label like `com` is a public suffix: keep the cookie to the
+                    // host instead of sharing it with every host under the suffix
+                    if !domain.contains('.') {
+                        log::debug!("Ignoring Domain={domain} for cookie {name}");
+                        continue;
+                    }
+                    if !domain_matches(&host, &domain, true) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.include_subdomains = true;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => {
+                    if let Some(date) = parse_cookie_date(value) {
+                        // An expiry of 0 would turn the cookie into a session cookie
+                        cookie.expires = date.max(1);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Max-Age takes precedence over Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = if max_age <= 0 { 1 } else { now + max_age };
+        }
+
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    /// Whether the cookie should be sent with a request for `url`.
+    pub fn matches(&self, url: &Url, now: i64) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let secure_scheme = matches!(url.scheme(), "wss" | "https");
+
+        !self.is_expired(now)
+            && (secure_scheme || !self.secure)
+            && domain_matches(
+                &host.to_ascii_lowercase(),
+                &self.domain,
+                self.include_subdomains,
+            )
+            && path_matches(url.path(), &self.path)
+    }
+}
+
+/// Parses an `Expires` date into Unix time with the lenient algorithm of RFC 6265, section
+/// 5.1.1, which accepts the formats servers actually send, like `Wed, 21-Oct-2015 07:28:00 GMT`.
+fn parse_cookie_date(date: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in date.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = cookie_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(parsed) = leading_digits(token, 1..=2) {
+                day = Some(parsed);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(index) = MONTHS.iter().position(|m| prefix.as_deref() == Some(*m)) {
+                month = Some(index as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(parsed) = leading_digits(token, 2..=4) {
+                year = Some(parsed);
+            }
+        }
+    }
+
+    let year = match year? {
+        year @ 70..=99 => year + 1900,
+        year @ 0..=69 => year + 2000,
+        year => year,
+    };
+    if year < 1601 {
+        return None;
+    }
+    let (hour, minute, second) = time?;
+    let date = NaiveDate::from_ymd_opt(year as i32, month?, day?)?;
+    Some(
+        date.and_hms_opt(hour, minute, second)?
+            .and_utc()
+            .timestamp(),
+    )
+}
+
+/// Parses `hh:mm:ss` with one or two digits each, allowing anything after the seconds.
+fn cookie_time(token: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = token.splitn(3, ':');
+    let mut field = || leading_digits(parts.next()?, 1..=2);
+    let (hour, minute, second) = (field()?, field()?, field()?);
+    // Only the seconds may be followed by other characters
+    let exact = token
+        .split(':')
+        .take(2)
+        .all(|part| part.bytes().all(|b| b.is_ascii_digit()));
+    (exact && hour < 24 && minute < 60 && second < 60).then_some((hour, minute, second))
+}
+
+/// The number at the start of `token`, when it has an allowed number of digits.
+fn leading_digits(token: &str, digits: std::ops::RangeInclusive<usize>) -> Option<u32> {
+    let len = token.bytes().take_while(u8::is_ascii_digit).count();
+    digits
+        .contains(&len)
+        .then(|| token[..len].parse().ok())
+        .flatten()
+}
+
+fn domain_matches(host: &str, domain: &str, include_subdomains: bool) -> bool {
+    host == domain
+        || (include_subdomains
+            && host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.')))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The path a cookie without a `Path` attribute applies to (RFC 6265, section 5.1.4).
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+/// Cookies loaded from, and saved back to, a Netscape (curl) format cookie file.
+pub struct CookieJar {
+    path: PathBuf,
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Reads the cookie file at `path`. A file that does not exist yet is an empty jar.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let cookies = match fs::read_to_string(path) {
+            Ok(contents) => contents.lines().filter_map(Cookie::parse_line).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        log::debug!("Loaded {} cookies from {}", cookies.len(), path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            cookies,
+        })
+    }
+
+    /// The cookies to send with a request for `url`, longest path first.
+    pub fn matching(&self, url: &Url) -> Vec<&Cookie> {
+        let now = Utc::now().timestamp();
+        let mut matching: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .collect();
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        matching
+    }
+
+    /// Stores the `Set-Cookie` headers of a response to a request for `url`.
+    ///
+    /// Returns whether the jar changed.
+    pub fn store<'a>(&mut self, url: &Url, set_cookies: impl IntoIterator<Item = &'a str>) -> bool {
+        let now = Utc::now().timestamp();
+        let mut changed = false;
+        for header in set_cookies {
+            let Some(cookie) = Cookie::parse_set_cookie(url, header, now) else {
+                log::debug!("Ignoring a cookie that this URL cannot set");
+                continue;
+            };
+            log::trace!("Storing cookie {} for {}", cookie.name, cookie.domain);
+            self.cookies.retain(|existing| {
+                existing.name != cookie.name
+                    || existing.domain != cookie.domain
+                    || existing.path != cookie.path
+            });
+            // A cookie set to expire in the past deletes the stored one
+            if !cookie.is_expired(now) {
+                self.cookies.push(cookie);
+            }
+            changed = true;
+        }
+        changed
+    }
+
+    /// Writes the jar back to its file, dropping expired cookies.
+    pub fn save(&self) -> io::Result<()> {
+        let now = Utc::now().timestamp();
+        let mut contents = HEADER.to_string();
+        for cookie in self.cookies.iter().filter(|cookie| !cookie.is_expired(now)) {
+            contents.push_str(&cookie.to_line());
+        }
+        fs::write(&self.path, contents)
+    }
+}
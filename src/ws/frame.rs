@@ -0,0 +1,165 @@
+//! RFC 6455 frames as bytes, for captured and hand-built frames.
+//!
+//! Headers are parsed and written by tungstenite, so a frame reads the same here as on a
+//! live connection. The checks tungstenite makes while reading a connection are in
+//! [`validate`] and [`Sequence`], with the same errors.
+
+use std::{fmt, io::Cursor};
+
+use tungstenite::{
+    error::ProtocolError,
+    protocol::frame::coding::{Control, Data, OpCode},
+};
+
+pub use tungstenite::protocol::frame::{Frame, FrameHeader};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The input ends before the frame does; `needed` bytes are required in total, when the
+    /// header says how many.
+    Incomplete { needed: Option<usize> },
+    /// The frame breaks the protocol, as tungstenite would report it.
+    Protocol(ProtocolError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incomplete { needed: None } => write!(f, "truncated frame header"),
+            Self::Incomplete {
+                needed: Some(needed),
+            } => write!(f, "truncated frame, {needed} bytes needed"),
+            Self::Protocol(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<ProtocolError> for FrameError {
+    fn from(e: ProtocolError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+/// Decodes the frame at the start of `input`, returning it with the number of bytes it took.
+///
+/// The payload is unmasked, and the mask key is kept in the header.
+pub fn decode(input: &[u8]) -> Result<(Frame, usize), FrameError> {
+    let mut cursor = Cursor::new(input);
+    let (header, len) = match FrameHeader::parse(&mut cursor) {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => return Err(FrameError::Incomplete { needed: None }),
+        Err(tungstenite::Error::Protocol(e)) => return Err(e.into()),
+        Err(e) => unreachable!("reading from memory cannot fail: {e}"),
+    };
+
+    let start = cursor.position() as usize;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| start.checked_add(len))
+        .unwrap_or(usize::MAX);
+    let payload = input
+        .get(start..end)
+        .ok_or(FrameError::Incomplete { needed: Some(end) })?
+        .to_vec();
+    let payload = match header.mask {
+        Some(_) => apply_mask(&header, payload),
+        None => payload,
+    };
+    Ok((Frame::from_payload(header, payload), end))
+}
+
+/// Encodes `frame`, masking its payload when the header has a key.
+pub fn encode(frame: Frame) -> Vec<u8> {
+    let mut output = Vec::with_capacity(frame.len());
+    frame
+        .format(&mut output)
+        .expect("writing to memory cannot fail");
+    output
+}
+
+/// Masks or unmasks `payload` with the key in `header`.
+///
+/// tungstenite only masks while formatting a frame, so this formats one and keeps the
+/// payload.
+fn apply_mask(header: &FrameHeader, payload: Vec<u8>) -> Vec<u8> {
+    let header_len = header.len(payload.len() as u64);
+    let mut output = encode(Frame::from_payload(header.clone(), payload));
+    output.split_off(header_len)
+}
+
+/// Checks a single frame the way tungstenite does when reading it from a connection.
+pub fn validate(frame: &Frame) -> Result<(), FrameError> {
+    let header = frame.header();
+    if header.rsv1 || header.rsv2 || header.rsv3 {
+        return Err(ProtocolError::NonZeroReservedBits.into());
+    }
+    match header.opcode {
+        OpCode::Control(Control::Reserved(opcode)) => {
+            Err(ProtocolError::UnknownControlFrameType(opcode).into())
+        }
+        OpCode::Data(Data::Reserved(opcode)) => {
+            Err(ProtocolError::UnknownDataFrameType(opcode).into())
+        }
+        OpCode::Control(_) if !header.is_final => Err(ProtocolError::FragmentedControlFrame.into()),
+        OpCode::Control(_) if frame.payload().len() > 125 => {
+            Err(ProtocolError::ControlFrameTooBig.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the frames sent one way on a connection follow each other correctly.
+#[derive(Debug, Default)]
+pub struct Sequence {
+    masked: Option<bool>,
+    fragmented: bool,
+}
+
+impl Sequence {
+    /// Clients mask every frame and servers none. When `masked` does not say which end sent
+    /// the frames, the first frame decides.
+    pub fn new(masked: Option<bool>) -> Self {
+        Self {
+            masked,
+            fragmented: false,
+        }
+    }
+
+    pub fn check(&mut self, frame: &Frame) -> Result<(), FrameError> {
+        let header = frame.header();
+        match (
+            *self.masked.get_or_insert(header.mask.is_some()),
+            header.mask,
+        ) {
+            (true, None) => return Err(ProtocolError::UnmaskedFrameFromClient.into()),
+            (false, Some(_)) => return Err(ProtocolError::MaskedFrameFromServer.into()),
+            _ => {}
+        }
+        let OpCode::Data(data) = header.opcode else {
+            return Ok(());
+        };
+        match (data, self.fragmented) {
+            (Data::Continue, false) => return Err(ProtocolError::UnexpectedContinueFrame.into()),
+            (Data::Continue, true) => {}
+            (data, true) => return Err(ProtocolError::ExpectedFragment(data).into()),
+            (_, false) => {}
+        }
+        self.fragmented = !header.is_final;
+        Ok(())
+    }
+}
+
+/// The name of a defined opcode, like `text` or `ping`.
+pub fn opcode_name(opcode: OpCode) -> Option<&'static str> {
+    match opcode {
+        OpCode::Data(Data::Continue) => Some("continuation"),
+        OpCode::Data(Data::Text) => Some("text"),
+        OpCode::Data(Data::Binary) => Some("binary"),
+        OpCode::Control(Control::Close) => Some("close"),
+        OpCode::Control(Control::Ping) => Some("ping"),
+        OpCode::Control(Control::Pong) => Some("pong"),
+        OpCode::Data(Data::Reserved(_)) | OpCode::Control(Control::Reserved(_)) => None,
+    }
+}
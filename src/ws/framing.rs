@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tungstenite::Message;
+
+/// How received messages are delimited in the byte stream `ws` outputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Each message is followed by `\n`.
+    #[default]
+    Newline,
+    /// Messages are written back to back.
+    None,
+    /// Each message is followed by a NUL byte.
+    Nul,
+    /// Each message is preceded by its length as a big-endian `u32`.
+    LengthPrefixed,
+    /// Each message is written as one line of JSON: a string for text messages and
+    /// `{"binary": "<base64>"}` for binary ones.
+    NdjsonEscaped,
+}
+
+impl Framing {
+    pub const NAMES: [&'static str; 5] = [
+        "newline",
+        "none",
+        "nul",
+        "length-prefixed",
+        "ndjson-escaped",
+    ];
+
+    /// The bytes to write for `message`, or `None` for control messages.
+    pub fn encode(self, message: Message) -> Option<Vec<u8>> {
+        let (text, payload) = match message {
+            Message::Text(text) => (true, text.into_bytes()),
+            Message::Binary(data) => (false, data),
+            _ => return None,
+        };
+
+        Some(match self {
+            Self::Newline => with_suffix(payload, b'\n'),
+            Self::None => payload,
+            Self::Nul => with_suffix(payload, 0),
+            Self::LengthPrefixed => {
+                let mut data = Vec::with_capacity(payload.len() + 4);
+                data.extend((payload.len() as u32).to_be_bytes());
+                data.extend(payload);
+                data
+            }
+            Self::NdjsonEscaped => {
+                let line = if text {
+                    serde_json::Value::String(String::from_utf8(payload).unwrap_or_default())
+                } else {
+                    serde_json::json!({ "binary": BASE64.encode(payload) })
+                };
+                with_suffix(line.to_string().into_bytes(), b'\n')
+            }
+        })
+    }
+}
+
+fn with_suffix(mut data: Vec<u8>, suffix: u8) -> Vec<u8> {
+    data.push(suffix);
+    data
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(Self::Newline),
+            "none" => Ok(Self::None),
+            "nul" => Ok(Self::Nul),
+            "length-prefixed" => Ok(Self::LengthPrefixed),
+            "ndjson-escaped" => Ok(Self::NdjsonEscaped),
+            _ => Err(format!(
+                "unknown framing {s:?}, expected one of: {}",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! WebSocket connections captured in HAR files by browser developer tools.
+//!
+//! Chrome and Firefox list each connection as an entry with its frames in
+//! `_webSocketMessages`: `{type: "send" | "receive", time, opcode, data}`, where `time` is
+//! in seconds since the Unix epoch and binary data is base64.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tungstenite::Message;
+
+use super::recording::Direction;
+
+const TEXT: u64 = 1;
+const BINARY: u64 = 2;
+
+/// A WebSocket connection found in a HAR file.
+#[derive(Debug, Clone)]
+pub struct HarConnection {
+    pub url: String,
+    /// `startedDateTime` of the entry, as written in the file.
+    pub started: Option<String>,
+    pub status: Option<u16>,
+    pub request_headers: Vec<(String, String)>,
+    pub frames: Vec<HarFrame>,
+}
+
+impl HarConnection {
+    /// The subprotocols the browser offered.
+    pub fn subprotocols(&self) -> Vec<String> {
+        self.request_headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Sec-WebSocket-Protocol"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(|protocol| protocol.trim().to_string())
+            .filter(|protocol| !protocol.is_empty())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HarFrame {
+    pub direction: Direction,
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+    pub message: Message,
+}
+
+/// Finds the WebSocket connections in a parsed HAR file, in the order they appear.
+pub fn connections(har: &serde_json::Value) -> Result<Vec<HarConnection>, String> {
+    let entries = har["log"]["entries"]
+        .as_array()
+        .ok_or("not a HAR file: log.entries is missing")?;
+
+    let mut connections = vec![];
+    for (index, entry) in entries.iter().enumerate() {
+        let url = entry["request"]["url"].as_str().unwrap_or_default();
+        let websocket = entry.get("_webSocketMessages").is_some()
+            || entry["_resourceType"] == "websocket"
+            || url.starts_with("ws://")
+            || url.starts_with("wss://");
+        if !websocket {
+            continue;
+        }
+
+        let frames = entry["_webSocketMessages"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|frame| self::frame(frame).transpose())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("entry {index}: {e}"))?;
+
+        connections.push(HarConnection {
+            url: url.to_string(),
+            started: entry["startedDateTime"].as_str().map(str::to_string),
+            status: entry["response"]["status"]
+                .as_u64()
+                .and_then(|status| u16::try_from(status).ok()),
+            request_headers: entry["request"]["headers"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|header| {
+                    Some((
+                        header["name"].as_str()?.to_string(),
+                        header["value"].as_str().unwrap_or_default().to_string(),
+                    ))
+                })
+                .collect(),
+            frames,
+        });
+    }
+    Ok(connections)
+}
+
+/// Reads one `_webSocketMessages` item, skipping control frames.
+fn frame(frame: &serde_json::Value) -> Result<Option<HarFrame>, String> {
+    let direction = match frame["type"].as_str() {
+        Some("send") => Direction::Sent,
+        Some("receive") => Direction::Received,
+        other => return Err(format!("unknown message type {other:?}")),
+    };
+    let time = frame["time"]
+        .as_f64()
+        .filter(|time| time.is_finite())
+        .ok_or("message without a time")?;
+    let data = frame["data"].as_str().unwrap_or_default();
+    let message = match frame["opcode"].as_u64() {
+        Some(TEXT) => Message::Text(data.to_string()),
+        Some(BINARY) => Message::Binary(
+            BASE64
+                .decode(data)
+                .map_err(|e| format!("binary message is not base64: {e}"))?,
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(HarFrame {
+        direction,
+        time,
+        message,
+    }))
+}
@@ -0,0 +1,69 @@
+/// Request headers in the order they are sent. A name may appear more than once.
+///
+/// Names are compared case-insensitively but sent as given.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a header called `name` is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.iter().any(|(k, _)| k.eq_ignore_ascii_case(name))
+    }
+
+    /// The value of the last header called `name`, for extending it in place.
+    pub fn last_mut(&mut self, name: &str) -> Option<&mut String> {
+        self.0
+            .iter_mut()
+            .rev()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    /// Adds a header after the existing ones, even if one of the same name exists.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    /// Replaces every header called `name` with a single one, at the position of the first.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        match self
+            .0
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(&name))
+        {
+            Some(index) => {
+                self.0[index] = (name.clone(), value.into());
+                let mut i = 0;
+                self.0.retain(|(k, _)| {
+                    i += 1;
+                    i - 1 <= index || !k.eq_ignore_ascii_case(&name)
+                });
+            }
+            None => self.append(name, value),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for Headers {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
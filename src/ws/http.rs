@@ -0,0 +1,130 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use url::Url;
+
+use super::{
+    client::{redact_url, take_credentials},
+    connector::Connector,
+    headers::Headers,
+};
+
+/// How long a plain HTTP exchange may stall when the caller sets no timeout.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest response read, headers included. Negotiate responses are well under a
+/// kilobyte.
+const MAX_RESPONSE_LEN: u64 = 1024 * 1024;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Sends a bodyless `POST` and reads the whole response.
+///
+/// This is only meant for the small JSON exchanges some protocols make before upgrading,
+/// such as SignalR's negotiate request, so the connection is not kept alive. The connection
+/// is opened by `connector`, so it honors the same address and socket options as WebSockets.
+pub fn post(
+    url: &Url,
+    headers: &Headers,
+    timeout: Option<Duration>,
+    connector: &mut Connector,
+) -> io::Result<HttpResponse> {
+    let mut headers = headers.clone();
+    let url = &take_credentials(url, &mut headers);
+    let host = url
+        .host_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    let target = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+
+    let mut request = format!(
+        "POST {target} HTTP/1.1\r\nHost: {host_header}\r\nContent-Length: 0\r\nConnection: close\r\n"
+    );
+    for (name, value) in headers.iter() {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request.push_str("\r\n");
+    log::debug!("POST {}", redact_url(url));
+
+    let tls = matches!(url.scheme(), "https" | "wss");
+    let mut stream = connector.open(host, port, tls).map_err(|e| match e {
+        tungstenite::Error::Io(e) => e,
+        e => io::Error::other(e),
+    })?;
+    stream.set_read_timeout(Some(timeout.unwrap_or(DEFAULT_TIMEOUT)))?;
+    let raw = exchange(&mut stream, &request)?;
+    parse_response(&raw)
+}
+
+fn exchange(mut stream: impl Read + Write, request: &str) -> io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let mut raw = vec![];
+    // One byte past the limit tells a response of exactly the limit from a longer one
+    (&mut stream)
+        .take(MAX_RESPONSE_LEN + 1)
+        .read_to_end(&mut raw)?;
+    if raw.len() as u64 > MAX_RESPONSE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP response larger than {MAX_RESPONSE_LEN} bytes"),
+        ));
+    }
+    Ok(raw)
+}
+
+fn parse_response(raw: &[u8]) -> io::Result<HttpResponse> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete HTTP response"))?;
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("invalid HTTP status line"))?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+
+    let body = &raw[end + 4..];
+    let body = if chunked {
+        dechunk(body).ok_or_else(|| invalid("invalid chunked body"))?
+    } else {
+        body.to_vec()
+    };
+    Ok(HttpResponse { status, body })
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
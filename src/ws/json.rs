@@ -0,0 +1,68 @@
+use nu_protocol::{Record, ShellError, Span, Value};
+
+/// Converts a Nushell value into JSON the same way `to json` does.
+#[allow(clippy::result_large_err)]
+pub fn value_to_json(value: &Value) -> Result<serde_json::Value, ShellError> {
+    Ok(match value {
+        Value::Nothing { .. } => serde_json::Value::Null,
+        Value::Bool { val, .. } => serde_json::Value::Bool(*val),
+        Value::Int { val, .. } => serde_json::Value::from(*val),
+        Value::Float { val, .. } => serde_json::Number::from_f64(*val)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Filesize { val, .. } => serde_json::Value::from(val.get()),
+        Value::Duration { val, .. } => serde_json::Value::from(*val),
+        Value::Date { val, .. } => serde_json::Value::String(val.to_rfc3339()),
+        Value::String { val, .. } | Value::Glob { val, .. } => {
+            serde_json::Value::String(val.clone())
+        }
+        Value::Binary { val, .. } => {
+            serde_json::Value::Array(val.iter().map(|b| serde_json::Value::from(*b)).collect())
+        }
+        Value::List { vals, .. } => serde_json::Value::Array(
+            vals.iter()
+                .map(value_to_json)
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        Value::Record { val, .. } => serde_json::Value::Object(
+            val.iter()
+                .map(|(k, v)| Ok((k.clone(), value_to_json(v)?)))
+                .collect::<Result<serde_json::Map<_, _>, ShellError>>()?,
+        ),
+        Value::CellPath { val, .. } => serde_json::Value::String(val.to_string()),
+        other => {
+            return Err(ShellError::CantConvert {
+                to_type: "JSON".into(),
+                from_type: other.get_type().to_string(),
+                span: other.span(),
+                help: None,
+            })
+        }
+    })
+}
+
+/// Converts parsed JSON into a Nushell value the same way `from json` does.
+pub fn json_to_value(json: serde_json::Value, span: Span) -> Value {
+    match json {
+        serde_json::Value::Null => Value::nothing(span),
+        serde_json::Value::Bool(b) => Value::bool(b, span),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::int(i, span),
+            None => Value::float(n.as_f64().unwrap_or(f64::NAN), span),
+        },
+        serde_json::Value::String(s) => Value::string(s, span),
+        serde_json::Value::Array(items) => Value::list(
+            items
+                .into_iter()
+                .map(|item| json_to_value(item, span))
+                .collect(),
+            span,
+        ),
+        serde_json::Value::Object(map) => Value::record(
+            map.into_iter()
+                .map(|(k, v)| (k, json_to_value(v, span)))
+                .collect::<Record>(),
+            span,
+        ),
+    }
+}
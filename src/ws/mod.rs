@@ -1 +1,15 @@
+pub mod cert;
 pub mod client;
+pub mod connector;
+pub mod cookies;
+pub mod frame;
+pub mod framing;
+pub mod har;
+pub mod headers;
+pub mod http;
+pub mod json;
+pub mod pcap;
+pub mod receipt;
+pub mod recording;
+pub mod server;
+pub mod session;
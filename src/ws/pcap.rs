@@ -0,0 +1,359 @@
+//! Reading TCP segments from pcap and pcapng capture files.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use chrono::{DateTime, Utc};
+
+const PCAP_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_NANOS: u32 = 0xA1B2_3C4D;
+const PCAPNG_SECTION: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER: u32 = 0x1A2B_3C4D;
+
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IPPROTO_TCP: u8 = 6;
+
+/// A TCP segment found in a capture.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub time: DateTime<Utc>,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub seq: u32,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub payload: Vec<u8>,
+}
+
+/// Reads the TCP segments of a pcap or pcapng file, in capture order. Packets that are not
+/// TCP over IPv4 or IPv6, or are IP fragments, are skipped.
+pub fn segments(data: &[u8]) -> Result<Vec<Segment>, String> {
+    let packets = match data
+        .get(..4)
+        .map(|magic| u32::from_le_bytes(magic.try_into().unwrap()))
+    {
+        Some(PCAPNG_SECTION) => pcapng_packets(data)?,
+        Some(_) => pcap_packets(data)?,
+        None => return Err("the file is too short to be a capture".into()),
+    };
+    Ok(packets
+        .into_iter()
+        .filter_map(|packet| segment(&packet))
+        .collect())
+}
+
+struct Packet<'a> {
+    time: DateTime<Utc>,
+    link_type: u32,
+    data: &'a [u8],
+}
+
+/// A little cursor over capture file fields in the file's byte order.
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], String> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| "the capture is truncated".to_string())
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+fn pcap_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    let magic = u32::from_le_bytes(data[..4].try_into().unwrap());
+    let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+        (PCAP_MICROS, _) => (false, false),
+        (PCAP_NANOS, _) => (false, true),
+        (_, PCAP_MICROS) => (true, false),
+        (_, PCAP_NANOS) => (true, true),
+        _ => return Err("not a pcap or pcapng file".into()),
+    };
+    let reader = Reader { data, big_endian };
+    let link_type = reader.u32(20)? & 0x0FFF_FFFF;
+
+    let mut packets = vec![];
+    let mut offset = 24;
+    while offset < data.len() {
+        let seconds = reader.u32(offset)? as i64;
+        let fraction = reader.u32(offset + 4)? as i64;
+        let captured = reader.u32(offset + 8)? as usize;
+        let nanos = if nanos { fraction } else { fraction * 1000 };
+        packets.push(Packet {
+            time: DateTime::from_timestamp_nanos(seconds * 1_000_000_000 + nanos),
+            link_type,
+            data: reader.bytes(offset + 16, captured)?,
+        });
+        offset += 16 + captured;
+    }
+    Ok(packets)
+}
+
+fn pcapng_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, String> {
+    let mut reader = Reader {
+        data,
+        big_endian: false,
+    };
+    // Link type and nanoseconds per timestamp unit of each interface of the current section
+    let mut interfaces: Vec<(u32, f64)> = vec![];
+    let mut packets = vec![];
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let block_type = reader.u32(offset)?;
+        if block_type == PCAPNG_SECTION {
+            let order = reader.bytes(offset + 8, 4)?;
+            reader.big_endian = match u32::from_le_bytes(order.try_into().unwrap()) {
+                PCAPNG_BYTE_ORDER => false,
+                order if order.swap_bytes() == PCAPNG_BYTE_ORDER => true,
+                _ => return Err("invalid pcapng byte order magic".into()),
+            };
+            interfaces.clear();
+        }
+        let length = reader.u32(offset + 4)? as usize;
+        // Type, length and trailing length, plus the fixed fields of the blocks that are read
+        let minimum = match block_type {
+            PCAPNG_SECTION => 28,
+            PCAPNG_INTERFACE => 20,
+            PCAPNG_ENHANCED_PACKET => 32,
+            PCAPNG_SIMPLE_PACKET => 16,
+            _ => 12,
+        };
+        if length < minimum || !length.is_multiple_of(4) {
+            return Err(format!("invalid pcapng block length {length}"));
+        }
+        if offset + length > data.len() {
+            return Err(format!("truncated pcapng block at byte {offset}"));
+        }
+        let body = offset + 8;
+
+        match block_type {
+            PCAPNG_INTERFACE => {
+                let link_type = reader.u16(body)? as u32;
+                let mut unit = 1_000.0;
+                let mut option = body + 8;
+                while option + 4 <= offset + length - 4 {
+                    let code = reader.u16(option)?;
+                    let len = reader.u16(option + 2)? as usize;
+                    if code == 0 {
+                        break;
+                    }
+                    if code == IF_TSRESOL {
+                        let resolution = reader.bytes(option + 4, 1)?[0];
+                        let exponent = (resolution & 0x7F) as i32;
+                        let base: f64 = if resolution & 0x80 != 0 { 2.0 } else { 10.0 };
+                        unit = 1e9 / base.powi(exponent);
+                    }
+                    option += 4 + len.div_ceil(4) * 4;
+                }
+                interfaces.push((link_type, unit));
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = reader.u32(body)? as usize;
+                let (link_type, unit) = *interfaces
+                    .get(interface)
+                    .ok_or("packet on an undeclared interface")?;
+                let timestamp = (reader.u32(body + 4)? as u64) << 32 | reader.u32(body + 8)? as u64;
+                let captured = reader.u32(body + 12)? as usize;
+                if captured > length - minimum {
+                    return Err(format!("packet data runs past its block at byte {offset}"));
+                }
+                packets.push(Packet {
+                    time: DateTime::from_timestamp_nanos((timestamp as f64 * unit) as i64),
+                    link_type,
+                    data: reader.bytes(body + 20, captured)?,
+                });
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let (link_type, _) = *interfaces.first().ok_or("packet before any interface")?;
+                let captured = (length - minimum).min(reader.u32(body)? as usize);
+                packets.push(Packet {
+                    time: DateTime::default(),
+                    link_type,
+                    data: reader.bytes(body + 4, captured)?,
+                });
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(packets)
+}
+
+/// Decodes the link, IP and TCP headers of a packet.
+fn segment(packet: &Packet) -> Option<Segment> {
+    let data = packet.data;
+    let ip = match packet.link_type {
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..)?,
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?);
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                return None;
+            }
+            data.get(offset + 2..)?
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+        _ => return None,
+    };
+
+    let (source, destination, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = ((ip[0] & 0x0F) as usize) * 4;
+            let total_len = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+            let fragment = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?);
+            // Skip fragments: more fragments follow, or this is not the first one
+            if fragment & 0x2000 != 0 || fragment & 0x1FFF != 0 || *ip.get(9)? != IPPROTO_TCP {
+                return None;
+            }
+            let source = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(12..16)?).ok()?);
+            let destination = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(16..20)?).ok()?);
+            // A short snaplen can cut the packet off inside its own header
+            let end = total_len.min(ip.len()).max(header_len);
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip.get(header_len..end)?,
+            )
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+            let source = Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(8..24)?).ok()?);
+            let destination = Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(24..40)?).ok()?);
+            let end = (40 + payload_len).min(ip.len());
+            let mut next = *ip.get(6)?;
+            let mut offset = 40;
+            // Hop-by-hop, routing and destination options headers
+            while matches!(next, 0 | 43 | 60) {
+                next = *ip.get(offset)?;
+                offset += (*ip.get(offset + 1)? as usize + 1) * 8;
+            }
+            if next != IPPROTO_TCP {
+                return None;
+            }
+            (
+                IpAddr::from(source),
+                IpAddr::from(destination),
+                ip.get(offset..end)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?);
+    let destination_port = u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let header_len = ((tcp.get(12)? >> 4) as usize) * 4;
+    let flags = *tcp.get(13)?;
+
+    Some(Segment {
+        time: packet.time,
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        seq,
+        syn: flags & 0x02 != 0,
+        fin: flags & 0x01 != 0,
+        rst: flags & 0x04 != 0,
+        payload: tcp.get(header_len..)?.to_vec(),
+    })
+}
+
+/// Puts the segments sent one way on a TCP connection back in order.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    next_seq: Option<u32>,
+    /// Segments that arrived ahead of the data before them.
+    pending: HashMap<u32, Vec<u8>>,
+}
+
+impl Reassembler {
+    /// Adds a segment and returns the data that is now contiguous, dropping retransmissions.
+    pub fn push(&mut self, segment: &Segment) -> Vec<u8> {
+        if segment.syn {
+            self.next_seq = Some(segment.seq.wrapping_add(1));
+            self.pending.clear();
+            return vec![];
+        }
+        if segment.payload.is_empty() {
+            return vec![];
+        }
+        // Without the handshake, start from the first data seen
+        let next = *self.next_seq.get_or_insert(segment.seq);
+        self.pending
+            .entry(segment.seq)
+            .and_modify(|payload| {
+                if segment.payload.len() > payload.len() {
+                    payload.clone_from(&segment.payload)
+                }
+            })
+            .or_insert_with(|| segment.payload.clone());
+
+        let mut next = next;
+        let mut data = vec![];
+        loop {
+            // A pending segment that starts at or before `next` and reaches past it
+            let found = self.pending.iter().find_map(|(seq, payload)| {
+                let skip = next.wrapping_sub(*seq);
+                ((skip as i32) >= 0 && (skip as usize) < payload.len()).then_some(*seq)
+            });
+            let Some(seq) = found else { break };
+            let payload = self.pending.remove(&seq).expect("found above");
+            let skip = next.wrapping_sub(seq) as usize;
+            data.extend_from_slice(&payload[skip..]);
+            next = next.wrapping_add((payload.len() - skip) as u32);
+        }
+        // Whatever is left before `next` was retransmitted
+        self.pending
+            .retain(|seq, _| (seq.wrapping_sub(next) as i32) > 0);
+        self.next_seq = Some(next);
+        data
+    }
+}
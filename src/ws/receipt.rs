@@ -0,0 +1,79 @@
+//! When and in what order messages arrived, for `ws --timestamps`.
+
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
+use nu_protocol::{ast::CellPath, Span, Value};
+
+use super::json::json_to_value;
+
+/// Numbers and times messages the moment they are read.
+#[derive(Debug, Default)]
+pub struct Receipts {
+    next: u64,
+}
+
+impl Receipts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stamps a message that has just been read.
+    pub fn stamp(&mut self) -> Receipt {
+        let receipt = Receipt {
+            seq: self.next,
+            received: Utc::now(),
+        };
+        self.next += 1;
+        receipt
+    }
+}
+
+/// The sequence number and wall-clock receive time of a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Receipt {
+    /// Counts the data messages of a connection, starting at 0.
+    pub seq: u64,
+    pub received: DateTime<Utc>,
+}
+
+impl Receipt {
+    /// The prefix written before a message in the byte stream: `<seq> <received> `, with the
+    /// latency added after the receive time when one is asked for, or `-` if it is unknown.
+    pub fn prefix(&self, latency: Option<Option<TimeDelta>>) -> String {
+        let received = self.received.to_rfc3339_opts(SecondsFormat::Micros, true);
+        match latency {
+            None => format!("{} {received} ", self.seq),
+            Some(None) => format!("{} {received} - ", self.seq),
+            Some(Some(latency)) => format!(
+                "{} {received} {:.3}ms ",
+                self.seq,
+                latency.num_microseconds().unwrap_or(i64::MAX) as f64 / 1000.0
+            ),
+        }
+    }
+
+    /// How long after the timestamp at `path` in the JSON `payload` the message arrived.
+    ///
+    /// Numbers are read as Unix time in seconds, milliseconds, microseconds or nanoseconds,
+    /// depending on their magnitude. Strings must be RFC 3339 dates.
+    pub fn latency(&self, payload: &[u8], path: &CellPath) -> Option<TimeDelta> {
+        let json = serde_json::from_slice(payload).ok()?;
+        let value = json_to_value(json, Span::unknown());
+        let sent = match value.follow_cell_path(&path.members).ok()?.as_ref() {
+            Value::Int { val, .. } => unix_time(*val as f64),
+            Value::Float { val, .. } => unix_time(*val),
+            Value::String { val, .. } => DateTime::parse_from_rfc3339(val).ok()?.to_utc(),
+            _ => return None,
+        };
+        Some(self.received - sent)
+    }
+}
+
+fn unix_time(time: f64) -> DateTime<Utc> {
+    let nanos = match time.abs() {
+        t if t < 1e11 => time * 1e9,
+        t if t < 1e14 => time * 1e6,
+        t if t < 1e17 => time * 1e3,
+        _ => time,
+    };
+    DateTime::from_timestamp_nanos(nanos as i64)
+}
@@ -0,0 +1,156 @@
+//! JSON-lines recordings of a conversation, written by `ws --record` and served by `ws replay`.
+//!
+//! Each line is one frame:
+//! `{"direction": "sent", "opcode": "text", "time": 0.25, "payload": "hello"}`.
+//! `time` is in seconds since recording started. Text payloads are stored as is, binary,
+//! ping and pong payloads as base64, and close frames have the reason as payload and a `code`.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::json;
+use tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+/// Which way a frame went, from the client's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Received => "received",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    pub direction: Direction,
+    /// When the frame went by, relative to the start of the recording.
+    pub time: Duration,
+    pub message: Message,
+}
+
+impl RecordedFrame {
+    pub fn to_json(&self) -> serde_json::Value {
+        let (opcode, payload) = match &self.message {
+            Message::Text(text) => ("text", text.clone()),
+            Message::Binary(data) => ("binary", BASE64.encode(data)),
+            Message::Ping(data) => ("ping", BASE64.encode(data)),
+            Message::Pong(data) => ("pong", BASE64.encode(data)),
+            Message::Close(frame) => {
+                let mut line = json!({
+                    "direction": self.direction.as_str(),
+                    "opcode": "close",
+                    "time": self.time.as_secs_f64(),
+                });
+                if let Some(frame) = frame {
+                    line["code"] = u16::from(frame.code).into();
+                    line["payload"] = frame.reason.as_ref().into();
+                }
+                return line;
+            }
+            Message::Frame(frame) => ("binary", BASE64.encode(frame.payload())),
+        };
+        json!({
+            "direction": self.direction.as_str(),
+            "opcode": opcode,
+            "time": self.time.as_secs_f64(),
+            "payload": payload,
+        })
+    }
+
+    pub fn from_json(line: &serde_json::Value) -> Result<Self, String> {
+        let direction = match line["direction"].as_str() {
+            Some("sent") => Direction::Sent,
+            Some("received") => Direction::Received,
+            _ => return Err("direction must be sent or received".into()),
+        };
+        let time = line["time"]
+            .as_f64()
+            .filter(|time| *time >= 0.0)
+            .ok_or("time must be a number of seconds")?;
+        let payload = line["payload"].as_str().unwrap_or_default();
+        let binary = || {
+            BASE64
+                .decode(payload)
+                .map_err(|e| format!("payload is not base64: {e}"))
+        };
+        let message = match line["opcode"].as_str() {
+            Some("text") => Message::Text(payload.to_string()),
+            Some("binary") => Message::Binary(binary()?),
+            Some("ping") => Message::Ping(binary()?),
+            Some("pong") => Message::Pong(binary()?),
+            Some("close") => Message::Close(line["code"].as_u64().map(|code| CloseFrame {
+                code: CloseCode::from(code as u16),
+                reason: Cow::Owned(payload.to_string()),
+            })),
+            _ => return Err("opcode must be text, binary, ping, pong or close".into()),
+        };
+        Ok(Self {
+            direction,
+            time: Duration::from_secs_f64(time),
+            message,
+        })
+    }
+}
+
+/// Appends the frames of a connection to a recording as they go by.
+pub struct Recorder {
+    file: Mutex<LineWriter<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates the recording at `path`, replacing any existing file. Times are measured
+    /// from now.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&self, direction: Direction, message: &Message) {
+        let frame = RecordedFrame {
+            direction,
+            time: self.start.elapsed(),
+            message: message.clone(),
+        };
+        let mut file = self.file.lock().expect("recording lock poisoned");
+        if let Err(e) = writeln!(file, "{}", frame.to_json()) {
+            log::warn!("Could not write to the recording: {e}");
+        }
+    }
+}
+
+/// Reads a recording, reporting the first invalid line.
+pub fn load(path: &Path) -> io::Result<Vec<RecordedFrame>> {
+    let invalid = |number: usize, e: String| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("line {number}: {e}"))
+    };
+    let mut frames = vec![];
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let json = serde_json::from_str(&line).map_err(|e| invalid(index + 1, e.to_string()))?;
+        frames.push(RecordedFrame::from_json(&json).map_err(|e| invalid(index + 1, e))?);
+    }
+    Ok(frames)
+}
@@ -0,0 +1,147 @@
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{HeaderValue, StatusCode},
+    WebSocket,
+};
+
+use super::client::POLL_INTERVAL;
+
+pub type ServerStream = WebSocket<TcpStream>;
+
+/// How long a client gets to send its upgrade request before the connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An upgraded connection accepted by one of the plugin's local servers.
+pub struct Accepted {
+    pub socket: ServerStream,
+    /// The request path, e.g. `/ocpp/CP001`.
+    pub path: String,
+    pub subprotocol: Option<String>,
+}
+
+/// Completes the server side of the handshake on `stream`.
+///
+/// The first subprotocol offered by the client that is also in `supported` is selected.
+/// When `supported` is not empty, clients offering none of them are refused with a 400.
+/// Clients that stay silent for [`HANDSHAKE_TIMEOUT`] are dropped, and the accepted socket
+/// then polls with the same read timeout as client sessions.
+#[allow(clippy::result_large_err)]
+pub fn accept(stream: TcpStream, supported: &[&str]) -> tungstenite::Result<Accepted> {
+    let mut path = String::new();
+    let mut subprotocol = None;
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let socket = tungstenite::accept_hdr(stream, |request: &Request, mut response: Response| {
+        path = request.uri().path().to_string();
+        if supported.is_empty() {
+            return Ok(response);
+        }
+
+        let offered = request
+            .headers()
+            .get_all("Sec-WebSocket-Protocol")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        log::debug!("Client at {path} offered subprotocols {offered:?}");
+
+        match offered.into_iter().find(|p| supported.contains(p)) {
+            Some(protocol) => {
+                response.headers_mut().insert(
+                    "Sec-WebSocket-Protocol",
+                    HeaderValue::from_str(protocol).expect("subprotocols are valid header values"),
+                );
+                subprotocol = Some(protocol.to_string());
+                Ok(response)
+            }
+            None => {
+                let mut refusal = ErrorResponse::new(Some(format!(
+                    "expected one of the subprotocols {}",
+                    supported.join(", ")
+                )));
+                *refusal.status_mut() = StatusCode::BAD_REQUEST;
+                Err(refusal)
+            }
+        }
+    })
+    .map_err(|e| match e {
+        tungstenite::HandshakeError::Failure(e) => e,
+        tungstenite::HandshakeError::Interrupted(_) => {
+            tungstenite::Error::Io(std::io::ErrorKind::WouldBlock.into())
+        }
+    })?;
+
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    Ok(Accepted {
+        socket,
+        path,
+        subprotocol,
+    })
+}
+
+/// Tells a local server and the connections it serves to finish.
+#[derive(Debug, Clone, Default)]
+pub struct Stop(Arc<AtomicBool>);
+
+impl Stop {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Stops the server once its output stream is dropped.
+pub struct StopOnDrop(Stop);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        log::debug!("Stopping server");
+        self.0 .0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Accepts connections on a background thread until `stop` is set, calling `serve` for each
+/// on a thread of its own.
+///
+/// `listener` must be non-blocking so that stopping is noticed; accepted sockets are made
+/// blocking again before they are served.
+pub fn spawn_accept_loop(
+    listener: TcpListener,
+    stop: Stop,
+    serve: impl Fn(TcpStream, SocketAddr) + Clone + Send + 'static,
+) -> StopOnDrop {
+    let guard = StopOnDrop(stop.clone());
+    thread::spawn(move || {
+        while !stop.is_set() {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    // Accepted sockets inherit non-blocking mode from the listener on some
+                    // platforms
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        log::warn!("Failed to configure connection from {peer}: {e}");
+                        continue;
+                    }
+                    let serve = serve.clone();
+                    thread::spawn(move || serve(stream, peer));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(e) => log::warn!("Failed to accept a connection: {e}"),
+            }
+        }
+    });
+    guard
+}
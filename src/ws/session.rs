@@ -0,0 +1,208 @@
+use nu_protocol::{LabeledError, Signals, Span};
+use std::time::{Duration, Instant};
+use tungstenite::{handshake::client::Response, Message};
+use url::Url;
+
+use super::{
+    client::{handshake, is_read_timeout, set_read_timeout, WebSocketStream, POLL_INTERVAL},
+    connector::Connector,
+    headers::Headers,
+};
+
+/// Outcome of waiting for the next frame on a [`Session`].
+pub enum Received {
+    /// A frame arrived.
+    Message(Message),
+    /// Nothing arrived before the requested instant.
+    Idle,
+    /// The peer closed the connection or the session deadline passed.
+    Closed,
+}
+
+/// A connection driven from a single thread, for request/response style protocols.
+///
+/// Unlike [`super::client::connect`], no reader thread is spawned: callers interleave
+/// sends and receives themselves, which is what protocols with correlation ids and
+/// heartbeats need.
+pub struct Session {
+    socket: WebSocketStream,
+    response: Response,
+    deadline: Option<Instant>,
+    signals: Signals,
+    span: Span,
+    closed: bool,
+}
+
+impl Session {
+    pub fn open(
+        url: &Url,
+        timeout: Option<Duration>,
+        headers: Headers,
+        subprotocols: &[String],
+        connector: Connector,
+        signals: Signals,
+        span: Span,
+    ) -> Result<Self, LabeledError> {
+        let (socket, response) = handshake(url, headers, subprotocols, connector).map_err(|e| {
+            LabeledError::new(format!("Failed to connect to WebSocket: {e}"))
+                .with_label("could not connect to this URL", span)
+        })?;
+
+        log::debug!("WebSocket handshake completed successfully");
+
+        set_read_timeout(&socket, Some(POLL_INTERVAL))
+            .map_err(|e| LabeledError::new(format!("Failed to configure socket: {e}")))?;
+
+        Ok(Self {
+            socket,
+            response,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            signals,
+            span,
+            closed: false,
+        })
+    }
+
+    /// The HTTP response to the upgrade request.
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+
+    /// The subprotocol the server selected, if any.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|v| v.to_str().ok())
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn signals(&self) -> &Signals {
+        &self.signals
+    }
+
+    /// Whether the `--max-time` deadline of the session has passed.
+    pub fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<(), LabeledError> {
+        log::trace!("Sending message: {message:?}");
+        self.write(message)
+    }
+
+    /// Sends a text message that carries credentials, logging only its length.
+    pub fn send_credentials(&mut self, text: impl Into<String>) -> Result<(), LabeledError> {
+        let text = text.into();
+        log::trace!("Sending message: <redacted, {} bytes>", text.len());
+        self.write(Message::Text(text))
+    }
+
+    fn write(&mut self, message: Message) -> Result<(), LabeledError> {
+        self.socket.send(message).map_err(|e| {
+            LabeledError::new(format!("Failed to send WebSocket message: {e}"))
+                .with_label("while talking to this endpoint", self.span)
+        })
+    }
+
+    pub fn send_text(&mut self, text: impl Into<String>) -> Result<(), LabeledError> {
+        self.send(Message::Text(text.into()))
+    }
+
+    pub fn send_json(&mut self, json: &serde_json::Value) -> Result<(), LabeledError> {
+        self.send_text(json.to_string())
+    }
+
+    /// Waits for the next frame until `until`, the session deadline or a signal, whichever
+    /// comes first.
+    ///
+    /// Pings are answered automatically and are not reported.
+    pub fn recv_until(&mut self, until: Option<Instant>) -> Result<Received, LabeledError> {
+        loop {
+            self.signals.check(&self.span)?;
+
+            if self.closed || self.timed_out() {
+                return Ok(Received::Closed);
+            }
+            if until.is_some_and(|until| Instant::now() >= until) {
+                return Ok(Received::Idle);
+            }
+
+            match self.socket.read() {
+                Ok(Message::Close(frame)) => {
+                    log::debug!("Received Close message: {frame:?}");
+                    self.closed = true;
+                    return Ok(Received::Closed);
+                }
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) | Ok(Message::Frame(_)) => continue,
+                Ok(message) => {
+                    log::trace!("Received message: {message:?}");
+                    return Ok(Received::Message(message));
+                }
+                Err(e) if is_read_timeout(&e) => continue,
+                Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => {
+                    self.closed = true;
+                    return Ok(Received::Closed);
+                }
+                Err(e) => {
+                    self.closed = true;
+                    return Err(LabeledError::new(format!("WebSocket read error: {e}"))
+                        .with_label("while reading from this endpoint", self.span));
+                }
+            }
+        }
+    }
+
+    /// Waits for the next Text or Binary frame, returning `None` once the session is over.
+    pub fn recv(&mut self) -> Result<Option<Message>, LabeledError> {
+        match self.recv_until(None)? {
+            Received::Message(message) => Ok(Some(message)),
+            Received::Idle | Received::Closed => Ok(None),
+        }
+    }
+
+    /// Waits for the next Text frame and parses it as JSON.
+    pub fn recv_json(&mut self) -> Result<Option<serde_json::Value>, LabeledError> {
+        loop {
+            match self.recv()? {
+                Some(Message::Text(text)) => return parse_json(&text, self.span).map(Some),
+                Some(other) => log::debug!("Ignoring non-text message: {other:?}"),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Closes the connection with a normal close code.
+    pub fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        log::debug!("Closing WebSocket session");
+        let _ = self.socket.close(Some(tungstenite::protocol::CloseFrame {
+            code: tungstenite::protocol::frame::coding::CloseCode::Normal,
+            reason: std::borrow::Cow::Borrowed("session finished"),
+        }));
+        let _ = self.socket.flush();
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Parses a text frame as JSON, reporting the frame on failure.
+pub fn parse_json(text: &str, span: Span) -> Result<serde_json::Value, LabeledError> {
+    serde_json::from_str(text).map_err(|e| {
+        LabeledError::new(format!("Received invalid JSON: {e}"))
+            .with_label("from this endpoint", span)
+            .with_help(format!("message was: {text}"))
+    })
+}